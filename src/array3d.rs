@@ -13,7 +13,7 @@ pub trait Dims : Clone + 'static {
 
     fn flat_index(&self, ijk: V3usize) -> usize {
         let dims = self.dims();
-        let strides = V3usize::new(dims.x * dims.y, dims.y, 1);
+        let strides = V3usize::new(dims.y * dims.z, dims.z, 1);
         ijk.dot(&strides)
     }
 
@@ -405,6 +405,16 @@ mod tests {
         assert_eq!(z1.iter().filter(|b| **b).count(), 8);
     }
 
+    #[test]
+    fn uneven_dims() {
+        let dims = V3usize::new(2, 3, 5);
+        let array = Array::generate_with_dims(dims, |ijk| ijk);
+
+        assert_eq!(array.volume(), 30);
+        assert!(array.indexed_iter().all(|(ijk, elem)| *elem == ijk));
+        assert_eq!(array[V3::new(1, 2, 4)], V3::new(1, 2, 4));
+    }
+
     #[test]
     fn copy_from() {
         type Array = ArrayOwned<i32, Dims>;
@@ -1,24 +1,81 @@
-
 use {
-    crate::array3d,
+    crate::{
+        array3d,
+        mesher::Direction,
+    },
+};
+
+pub mod model;
+pub mod registry;
+pub use {
+    model::Model,
+    registry::{BlockDef, Orient, Registry, Transparency},
 };
 
 pub type Slice   <'a> = array3d::ArraySlice   <'a, Block>;
 pub type SliceMut<'a> = array3d::ArraySliceMut<'a, Block>;
 
-#[repr(u8)]
-#[derive(Copy, Clone, PartialEq, Eq)]
-pub enum Block {
-    Empty,
-    Stone,
-    Soil,
-    Grass,
-    TreeTrunk,
+/// A block: its kind, by numeric ID, and a byte of state
+///
+/// What each ID means is up to the `Registry`, apart from 0, which is always
+/// empty space. The state holds the way the block faces in its low three
+/// bits, and a variant index in the rest.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Block {
+    id:    u8,
+    state: u8,
 }
 
+const FACING_MASK:   u8 = 0b111;
+const VARIANT_SHIFT: u8 = 3;
+
+/// The number of variants a block's state can tell apart
+pub const MAX_VARIANTS: u8 = 1 << (8 - VARIANT_SHIFT);
+
 impl Block {
+    pub const EMPTY: Block = Block { id: 0, state: 0 };
+
+    pub const fn from_id(id: u8) -> Block {
+        Block { id, state: 0 }
+    }
+
+    pub const fn from_parts(id: u8, state: u8) -> Block {
+        Block { id, state }
+    }
+
+    pub fn id(self) -> u8 {
+        self.id
+    }
+
+    pub fn state(self) -> u8 {
+        self.state
+    }
+
+    /// Whether two blocks are of the same kind, whatever their state
+    pub fn same_kind(self, other: Block) -> bool {
+        self.id == other.id
+    }
+
+    /// The direction the block's top faces, up unless it has been turned
+    pub fn facing(self) -> Direction {
+        Direction::from_u8(self.state & FACING_MASK).unwrap_or(Direction::ZOut)
+    }
+
+    pub fn with_facing(self, facing: Direction) -> Block {
+        Block { state: (self.state & !FACING_MASK) | facing as u8, ..self }
+    }
+
+    pub fn variant(self) -> u8 {
+        self.state >> VARIANT_SHIFT
+    }
+
+    pub fn with_variant(self, variant: u8) -> Block {
+        let variant = variant % MAX_VARIANTS;
+        Block { state: (self.state & FACING_MASK) | (variant << VARIANT_SHIFT), ..self }
+    }
+
     pub fn is_empty(&self) -> bool {
-        *self == Block::Empty
+        self.id == 0
     }
 
     pub fn is_nonempty(&self) -> bool {
@@ -26,3 +83,53 @@ impl Block {
     }
 }
 
+/// IDs from the stock block definitions, for tests which need a few
+/// different kinds of block
+#[cfg(test)]
+pub mod stock {
+    use super::{Block, Registry};
+
+    /// The stock block definitions, wherever the tests are run from
+    pub fn registry() -> Registry {
+        Registry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/blocks.txt")).unwrap()
+    }
+
+    pub const STONE:        Block = Block::from_id(1);
+    pub const SOIL:         Block = Block::from_id(2);
+    pub const GRASS:        Block = Block::from_id(3);
+    pub const TREE_TRUNK:   Block = Block::from_id(4);
+    pub const LAMP:         Block = Block::from_id(5);
+    pub const GLASS:        Block = Block::from_id(6);
+    pub const WATER:        Block = Block::from_id(7);
+    pub const LEAVES:       Block = Block::from_id(8);
+    pub const STONE_SLAB:   Block = Block::from_id(9);
+    pub const STONE_STAIRS: Block = Block::from_id(10);
+    pub const POST:         Block = Block::from_id(11);
+    pub const GRASS_TUFT:   Block = Block::from_id(12);
+    pub const FLOWER:       Block = Block::from_id(13);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state() {
+        let log = stock::TREE_TRUNK;
+        assert_eq!(log.facing(), Direction::ZOut);
+        assert_eq!(log.variant(), 0);
+
+        let turned = log.with_facing(Direction::XIn).with_variant(5);
+        assert_eq!(turned.facing(), Direction::XIn);
+        assert_eq!(turned.variant(), 5);
+        assert!(turned.same_kind(log) && turned != log);
+
+        let regrown = turned.with_variant(MAX_VARIANTS + 2);
+        assert_eq!(regrown.variant(), 2);
+        assert_eq!(regrown.facing(), Direction::XIn);
+        assert_eq!(regrown.with_facing(Direction::ZOut).with_variant(0), log);
+
+        // facing bits out of range read as up
+        assert_eq!(Block::from_parts(4, 7).facing(), Direction::ZOut);
+    }
+}
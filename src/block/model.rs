@@ -0,0 +1,124 @@
+
+use {
+    super::Orient,
+    crate::{
+        math::*,
+        mesher::Direction,
+    },
+};
+
+/// Model boxes are measured in this many parts of a block
+pub const UNITS: u8 = 16;
+
+/// An axis-aligned box within a block, in sixteenths from its least corner
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ModelBox {
+    pub mins: V3u8,
+    pub maxs: V3u8,
+}
+
+impl ModelBox {
+    /// A box between two corners, if it lies within the block and isn't flat
+    pub fn new(mins: V3u8, maxs: V3u8) -> Option<ModelBox> {
+        let valid = mins.iter().zip(maxs.iter()).all(|(lo, hi)| lo < hi && *hi <= UNITS);
+        if valid { Some(ModelBox { mins, maxs }) } else { None }
+    }
+
+    pub fn full() -> ModelBox {
+        ModelBox { mins: V3::zeros(), maxs: V3::repeat(UNITS) }
+    }
+
+    /// The box as it lies in a block turned as `orient` and `facing` say
+    pub fn turned(self, orient: Orient, facing: Direction) -> ModelBox {
+        // about the block's centre, in half-units so that it stays whole
+        let turn = |corner: V3u8| {
+            let centred = corner.map(|x| 2 * x as i32 - UNITS as i32);
+            orient.turn(facing, centred).map(|x| ((x + UNITS as i32) / 2) as u8)
+        };
+        let (a, b) = (turn(self.mins), turn(self.maxs));
+        ModelBox {
+            mins: a.zip_map(&b, u8::min),
+            maxs: a.zip_map(&b, u8::max),
+        }
+    }
+
+    /// The box in the world, for a block whose least corner is `block_mins`
+    pub fn in_world(self, block_mins: P3) -> Box3 {
+        let scale = 1. / UNITS as f32;
+        Box3::new_unchecked(
+            block_mins + self.mins.map(|x| x as f32 * scale),
+            block_mins + self.maxs.map(|x| x as f32 * scale),
+        )
+    }
+}
+
+/// The shape of a block
+#[derive(Clone, PartialEq, Debug)]
+pub enum Model {
+    /// Filling the whole block
+    Cube,
+    /// Made of boxes, as they lie in the block when it's upright
+    Boxes(Vec<ModelBox>),
+    /// Two quads crossing diagonally through the block, like a plant
+    Cross,
+}
+
+impl Model {
+    pub fn is_cube(&self) -> bool {
+        *self == Model::Cube
+    }
+
+    /// The boxes of a block turned as `orient` and `facing` say, which are
+    /// also what it collides with; cubes and crosses fill the block
+    pub fn boxes(&self, orient: Orient, facing: Direction) -> Vec<ModelBox> {
+        match self {
+            Model::Boxes(boxes) => boxes.iter().map(|b| b.turned(orient, facing)).collect(),
+            _                   => vec![ModelBox::full()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_box(mins: [u8; 3], maxs: [u8; 3]) -> ModelBox {
+        ModelBox::new(mins.into(), maxs.into()).unwrap()
+    }
+
+    #[test]
+    fn boxes_must_be_solid_and_inside() {
+        assert!(ModelBox::new(V3::new(0, 0, 0), V3::new(16, 16, 8)).is_some());
+        assert!(ModelBox::new(V3::new(0, 0, 8), V3::new(16, 16, 8)).is_none());
+        assert!(ModelBox::new(V3::new(4, 0, 0), V3::new(2, 16, 16)).is_none());
+        assert!(ModelBox::new(V3::new(0, 0, 0), V3::new(16, 17, 16)).is_none());
+    }
+
+    #[test]
+    fn turning() {
+        use Direction::*;
+        let step = model_box([0, 0, 8], [16, 8, 16]);
+
+        // stairs turned to face +X have their high step at -X
+        assert_eq!(step.turned(Orient::Horizontal, YOut), step);
+        assert_eq!(step.turned(Orient::Horizontal, XOut), model_box([0, 0, 8], [8, 16, 16]));
+        assert_eq!(step.turned(Orient::Horizontal, YIn), model_box([0, 8, 8], [16, 16, 16]));
+
+        // a slab turned upside down lies against the top
+        let slab = model_box([0, 0, 0], [16, 16, 8]);
+        assert_eq!(slab.turned(Orient::Facing, ZIn), model_box([0, 0, 8], [16, 16, 16]));
+        assert_eq!(slab.turned(Orient::Facing, XIn), model_box([8, 0, 0], [16, 16, 16]));
+
+        let boxes = Model::Boxes(vec![slab, step]).boxes(Orient::Horizontal, XIn);
+        assert_eq!(boxes, vec![slab, model_box([8, 0, 8], [16, 16, 16])]);
+        assert_eq!(Model::Cross.boxes(Orient::Fixed, ZOut), vec![ModelBox::full()]);
+    }
+
+    #[test]
+    fn in_world() {
+        let post = model_box([6, 6, 0], [10, 10, 16]);
+        let world = post.in_world(P3::new(1., -2., 3.));
+        assert_eq!(world.mins(), P3::new(1.375, -1.625, 3.));
+        assert_eq!(world.maxs(), P3::new(1.625, -1.375, 4.));
+    }
+}
@@ -0,0 +1,637 @@
+
+use {
+    super::{
+        Block, MAX_VARIANTS,
+        model::{self, Model, ModelBox},
+    },
+    crate::{
+        light,
+        math::*,
+        mesher::Direction,
+    },
+    std::{
+        collections::HashMap,
+        io,
+        path::Path,
+    },
+};
+
+type RGB = rgb::RGB<u8>;
+
+#[derive(Debug)]
+pub enum Error {
+    Loading(io::Error),
+    Parse { line: usize, message: String },
+    /// A block the game needs isn't defined
+    Undefined(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Loading(err)             => write!(f, "loading block definitions: {}", err),
+            Error::Parse { line, message }  => write!(f, "block definitions, line {}: {}", line, message),
+            Error::Undefined(name)          => write!(f, "no block named {:?} is defined", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Loading(io_err) => Some(io_err),
+            _                      => None,
+        }
+    }
+}
+
+/// How a block is turned as it is placed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Orient {
+    /// Always upright
+    Fixed,
+    /// Lying along the axis of the face it was placed against, like a log
+    Axis,
+    /// With its top toward whoever placed it
+    Facing,
+    /// Upright, with its front (+Y) toward whoever placed it, like stairs
+    Horizontal,
+}
+
+impl Orient {
+    /// Maps a vector in a block's own frame into the world, for a block
+    /// turned to `facing`
+    ///
+    /// Blocks are turned as little as possible: about whichever horizontal
+    /// axis takes their top to `facing`, or half a turn about X when upside
+    /// down. Horizontal blocks instead turn about Z to take their front there.
+    pub fn turn(self, facing: Direction, v: V3i32) -> V3i32 {
+        use Direction::*;
+        match (self, facing) {
+            (Orient::Horizontal, XOut) => V3::new( v.y, -v.x, v.z),
+            (Orient::Horizontal, YIn)  => V3::new(-v.x, -v.y, v.z),
+            (Orient::Horizontal, XIn)  => V3::new(-v.y,  v.x, v.z),
+            (Orient::Horizontal, _)    => v,
+            (_, ZOut) => v,
+            (_, ZIn)  => V3::new( v.x, -v.y, -v.z),
+            (_, XOut) => V3::new( v.z,  v.y, -v.x),
+            (_, XIn)  => V3::new(-v.z,  v.y,  v.x),
+            (_, YOut) => V3::new( v.x,  v.z, -v.y),
+            (_, YIn)  => V3::new( v.x, -v.z,  v.y),
+        }
+    }
+}
+
+/// How much of what's behind a block shows through it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transparency {
+    Opaque,
+    /// Each texel is either solid or clear, like leaves
+    Cutout,
+    /// Blended with what's behind, like glass or water
+    Blended,
+    /// Nothing at all to see; only empty space is clear
+    Clear,
+}
+
+/// How a kind of block looks and behaves
+#[derive(Clone, Debug)]
+pub struct BlockDef {
+    pub name:         String,
+    /// The atlas tile of each face, indexed by `Direction`
+    pub tiles:        [V2u8; 6],
+    /// Whether each face's texture is turned at random, indexed by `Direction`
+    pub rotate:       [bool; 6],
+    pub orient:       Orient,
+    /// How many variants there are, each using the tiles a column further
+    /// along the atlas than the last
+    pub variants:     u8,
+    pub tint:         RGB,
+    /// Whether it stops movement
+    pub solid:        bool,
+    /// Light passes through any block that isn't opaque
+    pub transparency: Transparency,
+    /// The level of the light it gives off, 0 for none
+    pub light:        u8,
+    pub model:        Model,
+}
+
+impl BlockDef {
+    /// Empty space, which is clear rather than cutout or blended since it
+    /// has no texels to draw; the mesher leaves it out before looking at how
+    /// it's drawn
+    fn empty() -> BlockDef {
+        BlockDef {
+            name:         "empty".into(),
+            tiles:        [V2::zeros(); 6],
+            rotate:       [false; 6],
+            orient:       Orient::Fixed,
+            variants:     1,
+            tint:         RGB::new(255, 255, 255),
+            solid:        false,
+            transparency: Transparency::Clear,
+            light:        0,
+            model:        Model::Cube,
+        }
+    }
+
+    /// Stands in for IDs with no definition, such as those in a world saved
+    /// with a different set of blocks, so they still show up and get in the
+    /// way
+    fn unknown() -> BlockDef {
+        BlockDef {
+            name:         "unknown".into(),
+            tiles:        [V2::zeros(); 6],
+            rotate:       [false; 6],
+            orient:       Orient::Fixed,
+            variants:     1,
+            tint:         RGB::new(255, 0, 255),
+            solid:        true,
+            transparency: Transparency::Opaque,
+            light:        0,
+            model:        Model::Cube,
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.transparency != Transparency::Opaque
+    }
+
+    /// Whether it fills its space and hides whatever is behind it, so that
+    /// light stops at it and faces against it needn't be drawn
+    pub fn is_opaque_cube(&self) -> bool {
+        !self.is_transparent() && self.model.is_cube()
+    }
+}
+
+const TOP:    &[Direction] = &[Direction::ZOut];
+const BOTTOM: &[Direction] = &[Direction::ZIn];
+const SIDE:   &[Direction] = &[Direction::XIn, Direction::XOut, Direction::YIn, Direction::YOut];
+const ALL:    &[Direction] = &[
+    Direction::ZOut, Direction::YOut, Direction::XOut,
+    Direction::ZIn,  Direction::YIn,  Direction::XIn,
+];
+
+/// A block definition as it is being read
+struct Partial {
+    line:         usize,
+    name:         Option<String>,
+    tiles:        [Option<V2u8>; 6],
+    rotate:       [bool; 6],
+    orient:       Orient,
+    variants:     u8,
+    tint:         RGB,
+    solid:        bool,
+    transparency: Transparency,
+    light:        u8,
+    boxes:        Vec<ModelBox>,
+    cross:        bool,
+}
+
+impl Partial {
+    fn new(line: usize) -> Partial {
+        Partial {
+            line,
+            name:         None,
+            tiles:        [None; 6],
+            rotate:       [false; 6],
+            orient:       Orient::Fixed,
+            variants:     1,
+            tint:         RGB::new(255, 255, 255),
+            solid:        true,
+            transparency: Transparency::Opaque,
+            light:        0,
+            boxes:        Vec::new(),
+            cross:        false,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn numbers<T: std::str::FromStr>(value: &str, n: usize) -> Result<Vec<T>, String> {
+            let parsed: Vec<T> = value.split_whitespace()
+                .map(|word| word.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("expected numbers, found {:?}", value))?;
+
+            if parsed.len() != n {
+                return Err(format!("expected {} numbers, found {:?}", n, value));
+            }
+            Ok(parsed)
+        }
+
+        fn boolean(value: &str) -> Result<bool, String> {
+            value.parse().map_err(|_| format!("expected true or false, found {:?}", value))
+        }
+
+        let mut set_tiles = |faces: &[Direction]| -> Result<(), String> {
+            let tile = numbers::<u8>(value, 2)?;
+            for dir in faces {
+                self.tiles[*dir as usize] = Some(V2::new(tile[0], tile[1]));
+            }
+            Ok(())
+        };
+
+        match key {
+            "tile"        => set_tiles(ALL)?,
+            "tile.top"    => set_tiles(TOP)?,
+            "tile.bottom" => set_tiles(BOTTOM)?,
+            "tile.side"   => set_tiles(SIDE)?,
+
+            "name" => {
+                if value.is_empty() {
+                    return Err("empty name".into());
+                }
+                self.name = Some(value.into());
+            }
+
+            "rotate" => {
+                self.rotate = [false; 6];
+                for word in value.split_whitespace() {
+                    let faces: &[Direction] = match word {
+                        "top"    => TOP,
+                        "bottom" => BOTTOM,
+                        "side"   => SIDE,
+                        "all"    => ALL,
+                        "none"   => &[],
+                        _        => return Err(format!("unknown face {:?}", word)),
+                    };
+                    for dir in faces {
+                        self.rotate[*dir as usize] = true;
+                    }
+                }
+            }
+
+            "orient" => {
+                self.orient = match value {
+                    "none"       => Orient::Fixed,
+                    "axis"       => Orient::Axis,
+                    "facing"     => Orient::Facing,
+                    "horizontal" => Orient::Horizontal,
+                    _            => return Err(format!("unknown orientation {:?}", value)),
+                };
+            }
+
+            "variants" => {
+                let n = numbers::<u8>(value, 1)?[0];
+                if n == 0 || n > MAX_VARIANTS {
+                    return Err(format!("variants must be from 1 to {}", MAX_VARIANTS));
+                }
+                self.variants = n;
+            }
+
+            "tint" => {
+                let rgb = numbers::<u8>(value, 3)?;
+                self.tint = RGB::new(rgb[0], rgb[1], rgb[2]);
+            }
+
+            "solid" => { self.solid = boolean(value)?; }
+
+            "transparent" => {
+                self.transparency = match value {
+                    "false"  => Transparency::Opaque,
+                    "cutout" => Transparency::Cutout,
+                    "true"   => Transparency::Blended,
+                    _        => return Err(format!("expected true, false or cutout, found {:?}", value)),
+                };
+            }
+
+            "light" => {
+                let level = numbers::<u8>(value, 1)?[0];
+                if level > light::MAX_LEVEL {
+                    return Err(format!("light must be from 0 to {}", light::MAX_LEVEL));
+                }
+                self.light = level;
+            }
+
+            "box" => {
+                let corners = numbers::<u8>(value, 6)?;
+                let model_box = ModelBox::new(
+                    V3::new(corners[0], corners[1], corners[2]),
+                    V3::new(corners[3], corners[4], corners[5]),
+                );
+                let model_box = model_box.ok_or_else(|| {
+                    format!("a box needs corners from 0 to {}, each less than the other", model::UNITS)
+                })?;
+                self.boxes.push(model_box);
+            }
+
+            "model" => {
+                self.cross = match value {
+                    "cube"  => false,
+                    "cross" => true,
+                    _       => return Err(format!("expected cube or cross, found {:?}", value)),
+                };
+            }
+
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<BlockDef, Error> {
+        let line = self.line;
+        let error = |message: &str| Error::Parse { line, message: message.into() };
+
+        let name = self.name.ok_or_else(|| error("block has no name"))?;
+
+        let mut tiles = [V2::zeros(); 6];
+        for (tile, given) in tiles.iter_mut().zip(self.tiles.iter()) {
+            *tile = given.ok_or_else(|| error("block is missing a face's tile"))?;
+        }
+
+        let model = match (self.cross, self.boxes.is_empty()) {
+            (true,  false) => return Err(error("a cross can't also have boxes")),
+            (true,  true)  => Model::Cross,
+            (false, true)  => Model::Cube,
+            (false, false) => Model::Boxes(self.boxes),
+        };
+
+        Ok(BlockDef {
+            name,
+            tiles,
+            rotate:       self.rotate,
+            orient:       self.orient,
+            variants:     self.variants,
+            tint:         self.tint,
+            solid:        self.solid,
+            transparency: self.transparency,
+            light:        self.light,
+            model,
+        })
+    }
+}
+
+/// The definitions of every kind of block, read from a file at startup
+///
+/// The format is described in the stock definitions file, `blocks.txt`.
+pub struct Registry {
+    defs:      Vec<Option<BlockDef>>,
+    by_name:   HashMap<String, Block>,
+    placeable: Vec<Block>,
+    unknown:   BlockDef,
+}
+
+impl Registry {
+    pub fn load(path: impl AsRef<Path>) -> Result<Registry, Error> {
+        let src = std::fs::read_to_string(path).map_err(Error::Loading)?;
+        Registry::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<Registry, Error> {
+        let mut defs: Vec<Option<BlockDef>> = vec![None; 256];
+        defs[0] = Some(BlockDef::empty());
+        let mut by_name = HashMap::new();
+        by_name.insert(BlockDef::empty().name, Block::EMPTY);
+
+        let mut section: Option<(u8, Partial)> = None;
+
+        let mut finish = |section: Option<(u8, Partial)>| -> Result<(), Error> {
+            if let Some((id, partial)) = section {
+                let line = partial.line;
+                let slot = &mut defs[id as usize];
+                if slot.is_some() {
+                    return Err(Error::Parse { line, message: format!("block {} is already defined", id) });
+                }
+
+                let def = partial.finish()?;
+                if by_name.insert(def.name.clone(), Block::from_id(id)).is_some() {
+                    return Err(Error::Parse {
+                        line,
+                        message: format!("more than one block is named {:?}", def.name),
+                    });
+                }
+                *slot = Some(def);
+            }
+            Ok(())
+        };
+
+        for (i, line) in src.lines().enumerate() {
+            let line_no = i + 1;
+            let error = |message: String| Error::Parse { line: line_no, message };
+
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let id: u8 = line[1 .. line.len() - 1].trim().parse()
+                    .map_err(|_| error(format!("bad block ID {:?}", line)))?;
+                if id == 0 {
+                    return Err(error("ID 0 is always empty".into()));
+                }
+
+                finish(section.take())?;
+                section = Some((id, Partial::new(line_no)));
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(at) => (line[.. at].trim(), line[at + 1 ..].trim()),
+                None     => return Err(error(format!("expected key = value, found {:?}", line))),
+            };
+
+            match &mut section {
+                Some((_, partial)) => partial.set(key, value).map_err(error)?,
+                None => return Err(error("key outside of any block".into())),
+            }
+        }
+        finish(section)?;
+
+        let placeable = (1 ..= 255)
+            .filter(|&id| defs[id as usize].is_some())
+            .map(Block::from_id)
+            .collect();
+
+        Ok(Registry { defs, by_name, placeable, unknown: BlockDef::unknown() })
+    }
+
+    pub fn get(&self, block: Block) -> &BlockDef {
+        self.defs[block.id() as usize].as_ref().unwrap_or(&self.unknown)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Block> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Looks up a block which must be defined
+    pub fn require(&self, name: &str) -> Result<Block, Error> {
+        self.by_name(name).ok_or_else(|| Error::Undefined(name.into()))
+    }
+
+    /// The blocks a player can hold and place, in selection order
+    pub fn placeable(&self) -> &[Block] {
+        &self.placeable
+    }
+
+    /// Steps through `placeable` from a block, wrapping at either end; blocks
+    /// outside it start from the first
+    pub fn cycle(&self, block: Block, steps: i32) -> Block {
+        let n = self.placeable.len() as i32;
+        if n == 0 {
+            return Block::EMPTY;
+        }
+
+        let index = self.placeable.iter()
+            .position(|b| b.same_kind(block))
+            .map_or(0, |i| i as i32 + steps);
+        self.placeable[index.rem_euclid(n) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::block::stock::*,
+    };
+
+    fn parse_error_line(src: &str) -> usize {
+        match Registry::parse(src) {
+            Err(Error::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn stock_blocks() {
+        let registry = registry();
+        assert_eq!(registry.by_name("stone"), Some(STONE));
+        assert_eq!(registry.by_name("soil"), Some(SOIL));
+        assert_eq!(registry.by_name("grass"), Some(GRASS));
+        assert_eq!(registry.by_name("tree trunk"), Some(TREE_TRUNK));
+        assert_eq!(registry.placeable(), &[
+            STONE, SOIL, GRASS, TREE_TRUNK, LAMP, GLASS, WATER, LEAVES,
+            STONE_SLAB, STONE_STAIRS, POST, GRASS_TUFT, FLOWER,
+        ]);
+
+        let grass = registry.get(GRASS);
+        assert_eq!(grass.tiles[Direction::ZOut as usize], V2::new(1, 0));
+        assert_eq!(grass.tiles[Direction::ZIn as usize], V2::new(2, 0));
+        assert_eq!(grass.tiles[Direction::XIn as usize], V2::new(3, 0));
+        assert_eq!(grass.rotate, [true, false, false, false, false, false]);
+        assert!(grass.solid && !grass.is_transparent());
+        assert_eq!(registry.get(TREE_TRUNK).orient, Orient::Axis);
+        assert_eq!(grass.light, 0);
+        assert!(registry.get(LAMP).light > 0);
+        assert_eq!(registry.get(GLASS).transparency, Transparency::Blended);
+        assert_eq!(registry.get(LEAVES).transparency, Transparency::Cutout);
+        assert_eq!(registry.get(Block::EMPTY).transparency, Transparency::Clear);
+        assert!(!registry.get(Block::EMPTY).is_opaque_cube());
+        assert!(!registry.get(WATER).solid);
+        assert!(grass.model.is_cube() && grass.is_opaque_cube());
+        assert!(!registry.get(STONE_SLAB).is_opaque_cube());
+        assert_eq!(registry.get(STONE_STAIRS).orient, Orient::Horizontal);
+        assert_eq!(registry.get(FLOWER).model, Model::Cross);
+
+        let empty = registry.get(Block::EMPTY);
+        assert!(!empty.solid && empty.is_transparent());
+    }
+
+    #[test]
+    fn definitions() {
+        let registry = Registry::parse("
+            # a comment
+            [7]
+            name   = glass  # another
+            tile   = 6 1
+            tint   = 200 220 255
+            rotate = side bottom
+            solid  = true
+            transparent = true
+            variants    = 3
+            light       = 9
+
+            [200]
+            name      = reed
+            tile      = 3 2
+            tile.side = 4 2
+            solid     = false
+            orient    = facing
+            transparent = cutout
+            model     = cross
+
+            [201]
+            name   = step
+            tile   = 0 0
+            orient = horizontal
+            box    = 0 0 0 16 16 8
+            box    = 0 0 8 16 8 16
+        ").unwrap();
+
+        let glass = registry.get(Block::from_id(7));
+        assert_eq!(glass.name, "glass");
+        assert_eq!(glass.tiles, [V2::new(6, 1); 6]);
+        assert_eq!(glass.tint, RGB::new(200, 220, 255));
+        assert_eq!(glass.rotate, [false, true, true, true, true, true]);
+        assert_eq!(glass.transparency, Transparency::Blended);
+        assert_eq!(glass.variants, 3);
+        assert_eq!(glass.light, 9);
+        assert_eq!(glass.orient, Orient::Fixed);
+
+        let reed = registry.get(Block::from_id(200));
+        assert_eq!(reed.tiles[Direction::ZOut as usize], V2::new(3, 2));
+        assert_eq!(reed.tiles[Direction::YOut as usize], V2::new(4, 2));
+        assert!(!reed.solid);
+        assert_eq!(reed.orient, Orient::Facing);
+        assert_eq!(reed.transparency, Transparency::Cutout);
+        assert_eq!(reed.model, Model::Cross);
+
+        let step = registry.get(Block::from_id(201));
+        assert_eq!(step.orient, Orient::Horizontal);
+        assert_eq!(step.model, Model::Boxes(vec![
+            ModelBox::new(V3::new(0, 0, 0), V3::new(16, 16, 8)).unwrap(),
+            ModelBox::new(V3::new(0, 0, 8), V3::new(16, 8, 16)).unwrap(),
+        ]));
+        assert!(glass.model.is_cube());
+
+        assert_eq!(registry.placeable(), &[Block::from_id(7), Block::from_id(200), Block::from_id(201)]);
+        assert_eq!(registry.cycle(Block::from_id(201), 1), Block::from_id(7));
+        assert_eq!(registry.cycle(Block::EMPTY, 5), Block::from_id(7));
+
+        // undefined IDs still have something to show
+        assert_eq!(registry.get(Block::from_id(9)).name, "unknown");
+        assert!(registry.require("stone").is_err());
+    }
+
+    #[test]
+    fn cycle() {
+        let registry = registry();
+        assert_eq!(registry.cycle(STONE, 1), SOIL);
+        assert_eq!(registry.cycle(STONE, -1), FLOWER);
+        assert_eq!(registry.cycle(GRASS, 14), TREE_TRUNK);
+        assert_eq!(registry.cycle(SOIL, 0), SOIL);
+        assert_eq!(registry.cycle(STONE.with_variant(1), 1), SOIL);
+        assert_eq!(registry.cycle(Block::EMPTY, 3), STONE);
+    }
+
+    #[test]
+    fn bad_definitions() {
+        assert_eq!(parse_error_line("name = stone"), 1);
+        assert_eq!(parse_error_line("[0]\nname = air\ntile = 0 0"), 1);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\n[x]"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0"), 3);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\ntint = 1 2 300"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nshiny = true"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nrotate = up"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\norient = sideways"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nvariants = 0"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nlight = 16"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\ntransparent = half"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nbox = 0 0 0 16 16"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nbox = 0 0 8 16 16 8"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nbox = 0 0 0 16 16 17"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nmodel = sphere"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\nmodel = cross\nbox = 0 0 0 1 1 1"), 1);
+
+        // every face needs a tile
+        assert_eq!(parse_error_line("\n[1]\nname = a\ntile.top = 0 0"), 2);
+        assert_eq!(parse_error_line("[1]\ntile = 0 0"), 1);
+
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\n[1]\nname = b\ntile = 1 0"), 4);
+        assert_eq!(parse_error_line("[1]\nname = a\ntile = 0 0\n[2]\nname = a\ntile = 1 0"), 4);
+        assert_eq!(parse_error_line("[2]\nname = a\ntile = 0 0\n\n[1]\nname = a\ntile = 1 0"), 5);
+        assert_eq!(parse_error_line("[1]\nname = empty\ntile = 0 0"), 1);
+    }
+}
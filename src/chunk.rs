@@ -3,6 +3,7 @@ use crate::{
     array3d,
     block::Block,
     math::*,
+    palette,
 };
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -60,7 +61,7 @@ pub const DIM:      i32 = 1 << DIM_LOG2;    //   8    16    32    64
 pub const DIM_MASK: i32 = DIM - 1;          //   7     f    1f    3f
 pub const VOLUME:   i32 = DIM * DIM * DIM;  // 512    4k   32k  256k  (Blocks, not bytes)
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct BlockCoords(V3i32);
 
 impl BlockCoords {
@@ -125,10 +126,23 @@ impl array3d::StaticDims for Dims {
 
 pub type Array     = array3d::ArrayOwned<Block, Dims>;
 pub type Slice<'a> = array3d::ArraySlice<'a, Block>;
+pub type Packed    = palette::PaletteArray<Block, Dims>;
+
+/// How a chunk's blocks are held in memory
+#[derive(Clone)]
+enum Storage {
+    /// One `Block` per voxel
+    Dense(Array),
+    /// A palette of the blocks present plus a small index per voxel
+    Packed(Packed),
+    /// The same block everywhere, with no per-voxel storage
+    Uniform(Block),
+}
 
 #[derive(Clone)]
 pub struct Chunk {
-    blocks: Array,
+    blocks:   Storage,
+    modified: bool,
 }
 
 impl From<Array> for Chunk {
@@ -137,27 +151,126 @@ impl From<Array> for Chunk {
     }
 }
 
+impl From<Packed> for Chunk {
+    fn from(packed: Packed) -> Chunk {
+        let blocks = match packed.palette() {
+            [block] => Storage::Uniform(*block),
+            _       => Storage::Packed(packed),
+        };
+        Chunk { blocks, modified: false }
+    }
+}
+
 impl Chunk {
     pub fn new(blocks: Array) -> Chunk {
-        Chunk { blocks }
+        Chunk { blocks: Storage::Dense(blocks), modified: false }
+    }
+
+    /// Creates a palette-compressed chunk with the same blocks as `blocks`,
+    /// or a uniform chunk if they are all the same
+    pub fn new_packed(blocks: &Array) -> Chunk {
+        Packed::from(blocks).into()
+    }
+
+    pub fn new_uniform(block: Block) -> Chunk {
+        Chunk { blocks: Storage::Uniform(block), modified: false }
+    }
+
+    /// The block filling the whole chunk, if it is stored as uniform
+    pub fn uniform(&self) -> Option<Block> {
+        match self.blocks {
+            Storage::Uniform(block) => Some(block),
+            _                       => None,
+        }
+    }
+
+    /// Approximate bytes allocated for the chunk's blocks
+    pub fn heap_size(&self) -> usize {
+        match &self.blocks {
+            Storage::Dense(array)  => array.volume() * std::mem::size_of::<Block>(),
+            Storage::Packed(array) => array.heap_size(),
+            Storage::Uniform(_)    => 0,
+        }
+    }
+
+    /// Whether the chunk has been mutated since it was made, loaded or last
+    /// marked clean
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn mark_clean(&mut self) {
+        self.modified = false;
     }
 
     pub fn is_empty(&self) -> bool {
+        if let Some(block) = self.uniform() {
+            return block.is_empty();
+        }
+
         self.iter()
             .all(|block| block.is_empty())
     }
-}
 
-impl std::ops::Deref for Chunk {
-    type Target = Array;
-    fn deref(&self) -> &Array {
-        &self.blocks
+    pub fn get(&self, ijk: V3usize) -> &Block {
+        match &self.blocks {
+            Storage::Dense(array)   => array.get(ijk),
+            Storage::Packed(array)  => array.get(ijk),
+            Storage::Uniform(block) => block,
+        }
+    }
+
+    /// Writes a single block, expanding a uniform chunk only if the block
+    /// differs from its filling
+    ///
+    /// This is the only way to change a block, as packed chunks can't lend
+    /// out their blocks mutably.
+    pub fn set(&mut self, ijk: V3u8, block: Block) {
+        if self.uniform() == Some(block) {
+            return;
+        }
+
+        self.modified = true;
+        let ijk = ijk.map(|x| x as usize);
+        if let Storage::Uniform(filling) = self.blocks {
+            self.blocks = Storage::Packed(Packed::new_filled(filling));
+        }
+
+        match &mut self.blocks {
+            Storage::Dense(array)  => *array.get_mut(ijk) = block,
+            Storage::Packed(array) => array.set(ijk, block),
+            Storage::Uniform(_)    => unreachable!(),
+        }
+    }
+
+    pub fn indices(&self) -> SpaceIter<usize> {
+        SpaceIter::new(V3::zeros(), V3::repeat(DIM as usize))
+    }
+
+    pub fn indexed_iter(&self) -> impl Iterator<Item = (V3usize, Block)> + '_ {
+        self.indices()
+            .map(move |ijk| (ijk, *self.get(ijk)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Block> + '_ {
+        self.indexed_iter()
+            .map(|(_, block)| block)
+    }
+
+    /// Copies the blocks starting at `offset` into `dst`, which determines the
+    /// extent of the copied region
+    pub fn copy_into(&self, offset: V3usize, dst: &mut array3d::ArraySliceMut<'_, Block>) {
+        match &self.blocks {
+            Storage::Dense(array)   => dst.copy_from(&array.slice(offset, dst.dims())),
+            Storage::Packed(array)  => array.copy_into(offset, dst),
+            Storage::Uniform(block) => dst.iter_mut().for_each(|b| *b = *block),
+        }
     }
 }
 
-impl std::ops::DerefMut for Chunk {
-    fn deref_mut(&mut self) -> &mut Array {
-        &mut self.blocks
+impl PartialEq for Chunk {
+    fn eq(&self, rhs: &Chunk) -> bool {
+        self.iter().eq(rhs.iter())
     }
 }
 
@@ -168,15 +281,43 @@ impl std::ops::Index<V3u8> for Chunk {
     }
 }
 
-impl std::ops::IndexMut<V3u8> for Chunk {
-    fn index_mut(&mut self, ijk: V3u8) -> &mut Block {
-        self.get_mut(ijk.map(|x| x as usize))
-    }
-}
-
 impl std::iter::FromIterator<Block> for Chunk {
     fn from_iter<T> (iter: T) -> Chunk where T: IntoIterator<Item = Block> {
         Chunk::new(Array::from_iter(iter))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::block::stock::*,
+    };
+
+    #[test]
+    fn uniform_expands_on_differing_write() {
+        let mut chunk = Chunk::new_uniform(STONE);
+        assert_eq!(chunk.heap_size(), 0);
+
+        chunk.set(V3::new(1, 2, 3), STONE);
+        assert!(chunk.uniform() == Some(STONE));
+        assert!(!chunk.is_modified());
+
+        chunk.set(V3::new(1, 2, 3), Block::EMPTY);
+        assert!(chunk.uniform().is_none());
+        assert!(chunk.is_modified());
+        assert!(chunk[V3::new(1, 2, 3)] == Block::EMPTY);
+        assert_eq!(chunk.iter().filter(|b| *b == STONE).count(), VOLUME as usize - 1);
+    }
+
+    #[test]
+    fn packing_detects_uniform() {
+        assert!(Chunk::new_packed(&Array::new_filled(SOIL)).uniform() == Some(SOIL));
+
+        let mut array = Array::new_filled(SOIL);
+        array[V3::new(0, 0, 1)] = GRASS;
+        let chunk = Chunk::new_packed(&array);
+        assert!(matches!(chunk.blocks, Storage::Packed(_)));
+        assert!(chunk[V3::new(0, 0, 1)] == GRASS);
+    }
+}
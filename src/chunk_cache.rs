@@ -2,109 +2,230 @@
 use {
     std::{
         borrow::Borrow,
-        collections::HashMap,
+        collections::{BTreeMap, HashMap},
+        fmt,
         hash::Hash,
     },
 };
 
 enum Entry<V> {
-    Cached(V),
+    Cached(V, u64),
     Borrowed
 }
 
 impl<V> Entry<V> {
-    fn acquire(&mut self) -> V {
+    fn acquire(&mut self) -> (V, u64) {
         match std::mem::replace(self, Entry::Borrowed) {
-            Entry::Borrowed      => { panic!(); }
-            Entry::Cached(value) => { value }
+            Entry::Borrowed             => { panic!(); }
+            Entry::Cached(value, stamp) => { (value, stamp) }
         }
     }
 
-    fn release(&mut self, value: V) {
-        if let Entry::Cached(_) = std::mem::replace(self, Entry::Cached(value)) {
+    fn release(&mut self, value: V, stamp: u64) {
+        if let Entry::Cached(..) = std::mem::replace(self, Entry::Cached(value, stamp)) {
             panic!();
         }
     }
 
-    fn unwrap(self) -> V {
+    fn unwrap(self) -> (V, u64) {
         match self {
-            Entry::Borrowed      => { panic!(); }
-            Entry::Cached(value) => { value }
+            Entry::Borrowed             => { panic!(); }
+            Entry::Cached(value, stamp) => { (value, stamp) }
         }
     }
 }
 
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stats {
+    pub hits:      u64,
+    pub misses:    u64,
+    pub evictions: u64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} hits, {} misses, {} evictions", self.hits, self.misses, self.evictions)
+    }
+}
+
+/// A cache of values which can be borrowed out and released back in
+///
+/// Unborrowed values are kept in least-recently-used order. Each is given a
+/// weight, such as its size in bytes, and once the total weight held exceeds
+/// `capacity` the oldest become available through `evict_excess`. Borrowed
+/// values never count against the capacity.
 pub struct Cache<K, V> {
-    map: HashMap<K, Entry<V>>,
-    borrows: usize,
+    map:      HashMap<K, Entry<V>>,
+    lru:      BTreeMap<u64, K>,
+    clock:    u64,
+    weigh:    fn(&V) -> usize,
+    held:     usize,
+    capacity: usize,
+    stats:    Stats,
 }
 
-impl<K, V> Cache<K, V> where K: Hash + Eq {
-    pub fn new() -> Cache<K, V> {
+impl<K, V> Cache<K, V> where K: Hash + Eq + Clone {
+    pub fn with_capacity(capacity: usize, weigh: fn(&V) -> usize) -> Cache<K, V> {
         Cache {
-            map: HashMap::new(),
-            borrows: 0,
+            map:     HashMap::new(),
+            lru:     BTreeMap::new(),
+            clock:   0,
+            weigh,
+            held:    0,
+            capacity,
+            stats:   Stats::default(),
         }
     }
 
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
     pub fn acquire<Q> (&mut self, key: &Q) -> Option<V>
         where K: Borrow<Q>,
               Q: Hash + Eq
     {
-        let value = self.map.get_mut(&key)?.acquire();
-        self.borrows += 1;
+        let entry = match self.map.get_mut(key) {
+            Some(entry) => entry,
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+        };
+
+        let (value, stamp) = entry.acquire();
+        self.lru.remove(&stamp);
+        self.held -= (self.weigh)(&value);
+        self.stats.hits += 1;
         Some(value)
     }
 
     pub fn release(&mut self, key: K, value: V) {
-        self.map.get_mut(&key).unwrap().release(value);
-        self.borrows -= 1;
+        let stamp = self.tick();
+        self.held += (self.weigh)(&value);
+        self.map.get_mut(&key).unwrap().release(value, stamp);
+        self.lru.insert(stamp, key);
     }
 
     pub fn insert(&mut self, key: K, value: V) {
-        let prev = self.map.insert(key, Entry::Cached(value));
+        let stamp = self.tick();
+        self.held += (self.weigh)(&value);
+        let prev = self.map.insert(key.clone(), Entry::Cached(value, stamp));
         assert!(prev.is_none());
+        self.lru.insert(stamp, key);
     }
 
     pub fn insert_and_acquire(&mut self, key: K) {
         let prev = self.map.insert(key, Entry::Borrowed);
         assert!(prev.is_none());
-        self.borrows += 1;
     }
 
-    pub fn remove<Q> (&mut self, key: &Q) -> V
+    pub fn contains<Q> (&self, key: &Q) -> bool
         where K: Borrow<Q>,
               Q: Hash + Eq
     {
-        self.map
-            .remove(&key)
-            .unwrap()
-            .unwrap()
-    }
-
-    pub fn borrowed(&self) -> bool {
-        self.borrows != 0
-    }
-
-    //pub fn iter(&self) -> impl Iterator<Item = (&K, Option<&V>)> {
-    //    self.map.iter()
-    //        .map(|(k, v)| (k, v.as_ref()))
-    //}
-
-    //pub fn iter_unborrowed(&self) -> impl Iterator<Item = (&K, &V)> {
-    //    self.iter()
-    //        .filter_map(|(k, v)| v.map(|v| (k, v)))
-    //}
-
-    //pub fn iter_all(&self) -> Option<impl Iterator<Item = (&K, &V)>> {
-    //    if self.borrowed() {
-    //        None
-    //    }
-    //    else {
-    //        let iter = self.iter()
-    //            .map(|(k, v)| (k, v.unwrap()));
-    //        Some(iter)
-    //    }
-    //}
+        self.map.contains_key(key)
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn iter_unborrowed_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.map.iter_mut()
+            .filter_map(|(k, entry)| match entry {
+                Entry::Cached(value, _) => Some((k, value)),
+                Entry::Borrowed         => None,
+            })
+    }
+
+    /// Removes the least recently used unborrowed value, if over capacity
+    pub fn evict_excess(&mut self) -> Option<(K, V)> {
+        if self.held <= self.capacity {
+            return None;
+        }
+
+        let stamp = *self.lru.keys().next()?;
+        let key = self.lru.remove(&stamp).unwrap();
+        let (value, _) = self.map.remove(&key).unwrap().unwrap();
+        self.held -= (self.weigh)(&value);
+        self.stats.evictions += 1;
+        Some((key, value))
+    }
+
+    /// Removes every entry that is not currently borrowed
+    pub fn evict_unborrowed(&mut self) {
+        self.map.retain(|_, entry| matches!(entry, Entry::Borrowed));
+        self.lru.clear();
+        self.held = 0;
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = Cache::with_capacity(2, |_| 1);
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        cache.insert(3, "three");
+
+        assert_eq!(cache.evict_excess(), Some((1, "one")));
+        assert_eq!(cache.evict_excess(), None);
+
+        // releasing makes an entry the most recently used
+        let two = cache.acquire(&2).unwrap();
+        cache.release(2, two);
+        cache.insert(4, "four");
+
+        assert_eq!(cache.evict_excess(), Some((3, "three")));
+        assert_eq!(cache.evict_excess(), None);
+        assert!(cache.contains(&2) && cache.contains(&4));
+    }
+
+    #[test]
+    fn borrowed_entries_are_not_evicted() {
+        let mut cache = Cache::with_capacity(0, |_| 1);
+        cache.insert(1, ());
+        cache.insert(2, ());
+
+        let one = cache.acquire(&1).unwrap();
+        assert_eq!(cache.evict_excess(), Some((2, ())));
+        assert_eq!(cache.evict_excess(), None);
+        assert!(cache.contains(&1));
+
+        cache.release(1, one);
+        assert_eq!(cache.evict_excess(), Some((1, ())));
+    }
+
+    #[test]
+    fn stats() {
+        let mut cache = Cache::with_capacity(0, |_| 1);
+        cache.insert(1, ());
+        assert!(cache.acquire(&1).is_some());
+        assert!(cache.acquire(&2).is_none());
+        cache.release(1, ());
+        cache.evict_excess();
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 1, 1));
+    }
+
+    #[test]
+    fn weighted() {
+        let mut cache = Cache::with_capacity(10, |v: &usize| *v);
+        cache.insert(1, 4);
+        cache.insert(2, 0);
+        cache.insert(3, 6);
+        assert_eq!(cache.evict_excess(), None);
+
+        cache.insert(4, 1);
+        assert_eq!(cache.held, 11);
+        assert_eq!(cache.evict_excess(), Some((1, 4)));
+        assert_eq!(cache.evict_excess(), None);
+        assert_eq!(cache.held, 7);
+    }
+}
@@ -1,7 +1,7 @@
 
 use {
     crate::{
-        block::Block,
+        block::{self, Block, Registry},
         chunk::{self, Array, Chunk, Coords as ChunkCoords},
         chunk_source::ChunkMaker,
         halton::*,
@@ -17,18 +17,30 @@ pub struct Test {
     height_noise: HeightNoise,
     forest_noise: ForestNoise,
 
+    stone:      Block,
+    soil:       Block,
+    grass:      Block,
+    tree_trunk: Block,
+
     //tree_buf: Vec<V2u8>,
 }
 
 impl Test {
-    pub fn new(seed: u64) -> Test {
+    /// Makes terrain from the blocks the registry names stone, soil, grass
+    /// and tree trunk
+    pub fn new(seed: u64, registry: &Registry) -> Result<Test, block::registry::Error> {
         let seed = seed as u32;
-        Test {
+        Ok(Test {
             height_noise: HeightNoise::new().set_seed(seed),
             forest_noise: ForestNoise::new().set_seed(seed),
 
+            stone:      registry.require("stone")?,
+            soil:       registry.require("soil")?,
+            grass:      registry.require("grass")?,
+            tree_trunk: registry.require("tree trunk")?,
+
             //tree_buf: Vec::new(),
-        }
+        })
     }
 }
 
@@ -55,7 +67,7 @@ impl ChunkMaker for Test {
         const CEILING: i32 =  4;
 
         if !(FLOOR .. CEILING).contains(&chunk_xyz.z) {
-            return vec![(chunk_coords, Array::new_filled(Block::Empty).into())];
+            return vec![(chunk_coords, Chunk::new_uniform(Block::EMPTY))];
         }
 
         let uns = |x| (x + 1.) * 0.5;
@@ -129,23 +141,23 @@ impl ChunkMaker for Test {
 
         (FLOOR .. CEILING)
             .map(|chunk_z| {
-                let chunk = Array::generate(|rel| {
+                let blocks = Array::generate(|rel| {
                     let rel = rel.map(|x| x as i32);
                     let ground_height = get_ground_height(rel.xy());
                     let block_height = chunk_z * chunk::DIM + rel.z;
                     let altitude = block_height - ground_height;
 
-                    use Block::*;
-                    if      altitude < -4       { Stone }
-                    else if altitude <  0       { Soil  }
-                    else if altitude <  1       { Grass }
-                    else if altitude > TREE_MAX { Empty }
+                    if      altitude < -4       { self.stone  }
+                    else if altitude <  0       { self.soil   }
+                    else if altitude <  1       { self.grass  }
+                    else if altitude > TREE_MAX { Block::EMPTY }
                     else {
                         let tree_height = get_tree_height(rel.xy());
-                        if altitude > tree_height { Empty     }
-                        else                      { TreeTrunk }
+                        if altitude > tree_height { Block::EMPTY    }
+                        else                      { self.tree_trunk }
                     }
-                }).into();
+                });
+                let chunk = Chunk::new_packed(&blocks);
 
                 let coords = ChunkCoords::new(chunk_xy.push(chunk_z).into());
                 (coords, chunk)
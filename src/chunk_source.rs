@@ -1,8 +1,16 @@
-
 use {
     crate::{
-        chunk::{Chunk, Coords},
+        block::Block,
+        chunk::{BlockCoords, Chunk, Coords},
         chunk_cache::*,
+        workers,
+    },
+    std::{
+        collections::HashMap,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
     },
 };
 
@@ -11,69 +19,455 @@ pub trait ChunkStore {
     fn store(&mut self, coords: Coords, chunk: &Chunk);
 }
 
-pub trait ChunkMaker {
+/// Generates chunks; called from worker threads, so it must be shareable
+pub trait ChunkMaker: Send + Sync + 'static {
 //  fn make(&self, coords: Coords) -> Chunk;
     fn make(&self, coords: Coords) -> Vec<(Coords, Chunk)>;
 }
 
+struct MakeJob {
+    coords:    Coords,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// The chunks made for a job, or `None` if it was cancelled before starting
+type Made = (Coords, Option<Vec<(Coords, Chunk)>>);
+
 pub struct Source<S, M> {
-    cache: Cache<Coords, Chunk>,
-    store: S,
-    maker: M,
+    cache:     Cache<Coords, Chunk>,
+    store:     S,
+    maker:     Arc<M>,
+    workers:   Option<workers::Pool<MakeJob, Made>>,
+    requests:  HashMap<Coords, Arc<AtomicBool>>,
+    in_flight: usize,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum LoadedFrom {
     Cache,
     Store,
-    Maker
 }
 
 impl<S, M> Source<S, M> where S: ChunkStore, M: ChunkMaker {
     pub fn new(store: S, maker: M) -> Self {
+        Self::with_cache_capacity(store, maker, usize::MAX)
+    }
+
+    /// Creates a source which caches at most about `capacity` bytes of chunks
+    /// beyond those currently borrowed
+    pub fn with_cache_capacity(store: S, maker: M, capacity: usize) -> Self {
         Source {
-            cache: Cache::new(),
+            cache: Cache::with_capacity(capacity, |chunk| {
+                std::mem::size_of::<Chunk>() + chunk.heap_size()
+            }),
             store,
-            maker
+            maker:     Arc::new(maker),
+            workers:   None,
+            requests:  HashMap::new(),
+            in_flight: 0,
+        }
+    }
+
+    pub fn cache_stats(&self) -> Stats {
+        self.cache.stats()
+    }
+
+    fn evict_excess(&mut self) {
+        while let Some((coords, chunk)) = self.cache.evict_excess() {
+            if chunk.is_modified() {
+                self.store.store(coords, &chunk);
+            }
+        }
+    }
+
+    /// Caches freshly made chunks which aren't already held or stored
+    fn insert_made(&mut self, requested: Coords, made: Vec<(Coords, Chunk)>) {
+        for (made_coords, chunk) in made {
+            // the maker may hand back neighbours we already hold, or which
+            // were edited and stored; those take precedence over fresh ones
+            if self.cache.contains(&made_coords) {
+                continue;
+            }
+
+            if made_coords != requested {
+                if let Some(stored) = self.store.load(made_coords) {
+                    self.cache.insert(made_coords, stored);
+                    continue;
+                }
+            }
+
+            self.cache.insert(made_coords, chunk);
         }
     }
 
-    pub fn load(&mut self, coords: Coords) -> (Chunk, LoadedFrom) {
+    fn try_load(&mut self, coords: Coords) -> Option<(Chunk, LoadedFrom)> {
         if let Some(chunk) = self.cache.acquire(&coords) {
-            return (chunk, LoadedFrom::Cache);
+            return Some((chunk, LoadedFrom::Cache));
         }
 
         if let Some(chunk) = self.store.load(coords) {
-            return (chunk, LoadedFrom::Store);
+            self.cache.insert_and_acquire(coords);
+            return Some((chunk, LoadedFrom::Store));
         }
 
-        self.maker.make(coords)
-            .drain(..)
-            .for_each(|(coords, chunk)| {
-                self.cache.insert(coords, chunk);
-            });
+        None
+    }
+
+    /// Loads a chunk if it is cached or stored, and otherwise queues it to be
+    /// made on a worker thread
+    ///
+    /// Once `poll` has taken in the made chunk, requesting it again returns
+    /// it from the cache. Repeated requests while it is being made are
+    /// ignored.
+    pub fn request(&mut self, coords: Coords) -> Option<(Chunk, LoadedFrom)> {
+        if let Some(loaded) = self.try_load(coords) {
+            self.requests.remove(&coords);
+            return Some(loaded);
+        }
+
+        if self.requests.contains_key(&coords) {
+            return None;
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.requests.insert(coords, cancelled.clone());
+        self.in_flight += 1;
+
+        let maker = &self.maker;
+        self.workers
+            .get_or_insert_with(|| {
+                let maker = maker.clone();
+                workers::Pool::new(
+                    "chunk-maker",
+                    workers::Pool::<MakeJob, Made>::default_thread_count(),
+                    move |job: MakeJob| {
+                        let made = if job.cancelled.load(Ordering::Relaxed) { None }
+                                   else { Some(maker.make(job.coords)) };
+                        (job.coords, made)
+                    }
+                )
+            })
+            .submit(MakeJob { coords, cancelled });
+
+        None
+    }
 
-        let chunk = self.cache.acquire(&coords).unwrap();
-        (chunk, LoadedFrom::Maker)
+    /// Cancels outstanding requests for which `cancel` returns true
+    ///
+    /// Jobs already running still complete and have their chunks cached.
+    pub fn cancel_requests(&mut self, mut cancel: impl FnMut(Coords) -> bool) {
+        self.requests.retain(|coords, cancelled| {
+            if cancel(*coords) {
+                cancelled.store(true, Ordering::Relaxed);
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    /// Takes in chunks made by the workers, returning the coordinates of
+    /// those requested and not cancelled
+    pub fn poll(&mut self) -> Vec<Coords> {
+        let results: Vec<Made> = match &self.workers {
+            Some(workers) => workers.try_iter().collect(),
+            None          => { return Vec::new(); }
+        };
+
+        let mut ready = Vec::new();
+        for (coords, made) in results {
+            self.in_flight -= 1;
+
+            let requested = match self.requests.get(&coords) {
+                Some(cancelled) => !cancelled.load(Ordering::Relaxed),
+                None            => false,
+            };
+
+            if let Some(made) = made {
+                self.insert_made(coords, made);
+                if requested {
+                    ready.push(coords);
+                }
+            }
+        }
+
+        self.evict_excess();
+        ready
+    }
+
+    /// Reads a block from a chunk which isn't borrowed, if it is cached or
+    /// stored
+    pub fn read_block(&mut self, block: BlockCoords) -> Option<Block> {
+        let (coords, offset) = block.chunk_and_offset();
+        let (chunk, _) = self.try_load(coords)?;
+        let value = chunk[offset];
+        self.store(coords, chunk);
+        Some(value)
+    }
+
+    /// Writes a block into a chunk which isn't borrowed, if it is cached or
+    /// stored, returning the block it replaced
+    ///
+    /// A chunk which hasn't been made yet is left alone rather than made on
+    /// this thread.
+    pub fn write_block(&mut self, block: BlockCoords, value: Block) -> Option<Block> {
+        let (coords, offset) = block.chunk_and_offset();
+        let (mut chunk, _) = self.try_load(coords)?;
+        let old = chunk[offset];
+        chunk.set(offset, value);
+        self.store(coords, chunk);
+        Some(old)
+    }
+
+    /// Number of jobs submitted to the workers and not yet polled
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
     }
 
     pub fn store(&mut self, coords: Coords, chunk: Chunk) {
         self.cache.release(coords, chunk);
-        //let chunk = self.cache.remove(coords);
-        //self.store.store(coords, chunk);
+        self.evict_excess();
     }
 
+    /// Writes a chunk that is still borrowed from the source, if modified
+    pub fn sync_borrowed(&mut self, coords: Coords, chunk: &mut Chunk) {
+        if chunk.is_modified() {
+            self.store.store(coords, chunk);
+            chunk.mark_clean();
+        }
+    }
+
+    /// Writes every modified chunk held by the cache to the store
     pub fn sync(&mut self) {
-        todo!();
-        //for (coords, chunk) in self.cache.iter_all().unwrap() {
-        //    if chunk.touched() { // TODO think
-        //        self.store.store(*coords, chunk)
-        //    }
-        //}
+        let store = &mut self.store;
+        for (coords, chunk) in self.cache.iter_unborrowed_mut() {
+            if chunk.is_modified() {
+                store.store(*coords, chunk);
+                chunk.mark_clean();
+            }
+        }
     }
 
+    /// Writes every modified chunk, then evicts everything not borrowed
     pub fn flush(&mut self) {
-        todo!();
+        self.sync();
+        self.cache.evict_unborrowed();
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            block::stock::*,
+            chunk::Array,
+            journal::{Change, Journal},
+            math::*,
+            stage::{Stage, StaleChunk},
+        },
+        std::{cell::RefCell, collections::HashMap, rc::Rc},
+    };
+
+    type Stored = Rc<RefCell<HashMap<Coords, Chunk>>>;
+
+    struct MapStore(Stored);
+
+    impl ChunkStore for MapStore {
+        fn load(&mut self, coords: Coords) -> Option<Chunk> {
+            let mut chunk = self.0.borrow().get(&coords)?.clone();
+            chunk.mark_clean();
+            Some(chunk)
+        }
+
+        fn store(&mut self, coords: Coords, chunk: &Chunk) {
+            self.0.borrow_mut().insert(coords, chunk.clone());
+        }
+    }
+
+    /// Makes a column of two chunks
+    struct Column;
+
+    impl ChunkMaker for Column {
+        fn make(&self, coords: Coords) -> Vec<(Coords, Chunk)> {
+            let base = coords.unwrap();
+            let base = Coords::new(P3::new(base.x, base.y, 0));
+            vec![
+                (base,            Array::new_filled(STONE).into()),
+                (base + V3::z(),  Array::new_filled(Block::EMPTY).into()),
+            ]
+        }
+    }
+
+    fn coords(x: i32, y: i32, z: i32) -> Coords {
+        Coords::new(P3::new(x, y, z))
+    }
+
+    fn poll_until_idle(source: &mut Source<MapStore, Column>) -> Vec<Coords> {
+        let mut ready = Vec::new();
+        while source.in_flight() > 0 {
+            ready.extend(source.poll());
+            std::thread::yield_now();
+        }
+        ready
+    }
+
+    /// Requests a chunk, waiting for the workers if it has to be made
+    fn fetch(source: &mut Source<MapStore, Column>, at: Coords) -> (Chunk, LoadedFrom) {
+        if let Some(loaded) = source.request(at) {
+            return loaded;
+        }
+
+        poll_until_idle(source);
+        source.request(at).unwrap()
+    }
+
+    #[test]
+    fn sync_writes_only_modified() {
+        let stored = Stored::default();
+        let mut source = Source::new(MapStore(stored.clone()), Column);
+
+        let (mut a, _) = fetch(&mut source, coords(0, 0, 0));
+        let (b, from) = fetch(&mut source, coords(0, 0, 1));
+        assert!(from == LoadedFrom::Cache);
+
+        a.set(V3::new(1, 2, 3), GRASS);
+        assert!(a.is_modified() && !b.is_modified());
+
+        source.store(coords(0, 0, 0), a);
+        source.store(coords(0, 0, 1), b);
+        source.sync();
+
+        let stored = stored.borrow();
+        assert_eq!(stored.len(), 1);
+        let a = &stored[&coords(0, 0, 0)];
+        assert!(a[V3::new(1, 2, 3)] == GRASS);
+    }
+
+    #[test]
+    fn flush_evicts_and_reloads_edits() {
+        let stored = Stored::default();
+        let mut source = Source::new(MapStore(stored.clone()), Column);
+
+        let (mut a, _) = fetch(&mut source, coords(0, 0, 0));
+        a.set(V3::new(0, 0, 0), Block::EMPTY);
+        source.store(coords(0, 0, 0), a);
+
+        let (mut b, _) = fetch(&mut source, coords(0, 0, 1));
+        b.set(V3::new(0, 0, 0), SOIL);
+        source.sync_borrowed(coords(0, 0, 1), &mut b);
+        assert!(!b.is_modified());
+
+        source.flush();
+        assert_eq!(stored.borrow().len(), 2);
+
+        // the borrowed chunk survives the flush
+        source.store(coords(0, 0, 1), b);
+
+        let (a, from) = fetch(&mut source, coords(0, 0, 0));
+        assert!(from == LoadedFrom::Store);
+        assert!(a[V3::new(0, 0, 0)] == Block::EMPTY);
+        assert!(!a.is_modified());
+        source.store(coords(0, 0, 0), a);
+    }
+
+    #[test]
+    fn eviction_writes_modified() {
+        let stored = Stored::default();
+        // room for one column of the dense chunks `Column` makes
+        let dense = Chunk::from(Array::new_filled(STONE));
+        let capacity = 2 * (std::mem::size_of::<Chunk>() + dense.heap_size());
+        let mut source = Source::with_cache_capacity(MapStore(stored.clone()), Column, capacity);
+
+        let (mut a, _) = fetch(&mut source, coords(0, 0, 0));
+        a.set(V3::new(0, 0, 0), Block::EMPTY);
+        source.store(coords(0, 0, 0), a);
+        assert!(stored.borrow().is_empty());
+
+        // making a second column pushes the first out of the cache
+        let (c, _) = fetch(&mut source, coords(1, 0, 0));
+        assert_eq!(stored.borrow().len(), 1);
+        assert!(stored.borrow()[&coords(0, 0, 0)][V3::new(0, 0, 0)] == Block::EMPTY);
+        assert!(source.cache_stats().evictions >= 2);
+        source.store(coords(1, 0, 0), c);
+    }
+
+    #[test]
+    fn requests_are_made_by_workers() {
+        let mut source = Source::new(MapStore(Stored::default()), Column);
+
+        assert!(source.request(coords(2, 3, 0)).is_none());
+        assert!(source.request(coords(2, 3, 0)).is_none());
+        assert_eq!(source.in_flight(), 1);
+
+        assert!(poll_until_idle(&mut source) == vec![coords(2, 3, 0)]);
+
+        let (a, from) = source.request(coords(2, 3, 0)).unwrap();
+        assert!(from == LoadedFrom::Cache);
+        assert!(a.iter().all(|block| block == STONE));
+
+        // the other chunk of the column came along with it
+        let (b, from) = source.request(coords(2, 3, 1)).unwrap();
+        assert!(from == LoadedFrom::Cache);
+        source.store(coords(2, 3, 0), a);
+        source.store(coords(2, 3, 1), b);
+    }
+
+    #[test]
+    fn cancelled_requests_are_not_reported() {
+        let mut source = Source::new(MapStore(Stored::default()), Column);
+
+        assert!(source.request(coords(0, 0, 0)).is_none());
+        assert!(source.request(coords(1, 0, 0)).is_none());
+        source.cancel_requests(|coords| coords.unwrap().x == 0);
+
+        assert!(poll_until_idle(&mut source) == vec![coords(1, 0, 0)]);
+    }
+
+    #[test]
+    fn undo_after_leaving_the_stage() {
+        let stored = Stored::default();
+        let mut source = Source::new(MapStore(stored.clone()), Column);
+        let at = coords(0, 0, 0);
+        let block = at.block_at_offset(V3::new(1, 2, 3));
+
+        let mut stage = Stage::new(1, at);
+        assert!(source.request(at).is_none());
+        poll_until_idle(&mut source);
+        let (mut chunk, _) = source.request(at).unwrap();
+
+        let mut journal = Journal::new(4);
+        journal.begin();
+        journal.record(Change { block, old: chunk[block.offset()], new: GRASS });
+        journal.commit();
+        chunk.set(block.offset(), GRASS);
+        stage.insert_absolute(at, chunk);
+
+        // the stage moves away, handing the edited chunk back to the source
+        for stale in stage.relocate(coords(10, 0, 0)) {
+            if let StaleChunk::Evicted { old_coords, value, .. } = stale {
+                source.store(old_coords, value);
+            }
+        }
+        assert!(stage.at_absolute(at).is_none());
+
+        for change in journal.undo().unwrap() {
+            assert_eq!(source.read_block(change.block), Some(GRASS));
+            assert_eq!(source.write_block(change.block, change.new), Some(GRASS));
+        }
+
+        source.sync();
+        assert!(stored.borrow()[&at][block.offset()] == STONE);
+        let (chunk, from) = source.request(at).unwrap();
+        assert!(from == LoadedFrom::Cache);
+        assert!(chunk[block.offset()] == STONE);
+        source.store(at, chunk);
+
+        // chunks which were never made aren't made to be written
+        assert_eq!(source.read_block(coords(5, 5, 5).block_mins()), None);
+        assert_eq!(source.write_block(coords(5, 5, 5).block_mins(), GRASS), None);
+        assert_eq!(source.in_flight(), 0);
+    }
+}
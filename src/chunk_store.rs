@@ -2,26 +2,318 @@
 use {
     crate::{
         block::Block,
-        chunk::{self, Array, Chunk, Coords},
+        chunk::{Chunk, Coords, Packed},
         chunk_source::ChunkStore,
+        math::*,
+    },
+    std::{
+        fs::{self, File, OpenOptions},
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
     },
 };
 
-pub struct Null {
+const REGION_DIM_LOG2: i32 = 3;
+const REGION_DIM:      i32 = 1 << REGION_DIM_LOG2;
+const REGION_DIM_MASK: i32 = REGION_DIM - 1;
+const REGION_VOLUME:   usize = (REGION_DIM * REGION_DIM * REGION_DIM) as usize;
+
+/// One (offset, length) pair per chunk slot, both little-endian `u32`s
+const HEADER_ENTRY_SIZE: usize = 8;
+const HEADER_SIZE:       usize = REGION_VOLUME * HEADER_ENTRY_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct RegionCoords(V3i32);
+
+impl RegionCoords {
+    fn containing(coords: Coords) -> RegionCoords {
+        RegionCoords(coords.unwrap().map(|x| x >> REGION_DIM_LOG2))
+    }
+
+    fn file_name(&self) -> String {
+        format!("r.{}.{}.{}.region", self.0.x, self.0.y, self.0.z)
+    }
+}
+
+fn slot_index(coords: Coords) -> usize {
+    let rel = coords.unwrap().map(|x| (x & REGION_DIM_MASK) as usize);
+    let dim = REGION_DIM as usize;
+    (rel.x * dim + rel.y) * dim + rel.z
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    offset: u32,
+    length: u32,
 }
 
-impl Null {
-    pub fn new() -> Null {
-        Null { }
+impl Slot {
+    fn is_vacant(&self) -> bool {
+        self.offset == 0
     }
 }
 
-impl ChunkStore for Null {
-    fn load(&mut self, _: Coords) -> Option<Chunk> {
-        None
+struct Header([Slot; REGION_VOLUME]);
+
+impl Header {
+    fn read(file: &mut File) -> io::Result<Header> {
+        let mut bytes = vec![0u8; HEADER_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut bytes)?;
+
+        let mut slots = [Slot::default(); REGION_VOLUME];
+        for (slot, entry) in slots.iter_mut().zip(bytes.chunks_exact(HEADER_ENTRY_SIZE)) {
+            let word = |i: usize| {
+                let mut le = [0u8; 4];
+                le.copy_from_slice(&entry[i .. i + 4]);
+                u32::from_le_bytes(le)
+            };
+            *slot = Slot { offset: word(0), length: word(4) };
+        }
+
+        Ok(Header(slots))
     }
 
-    fn store(&mut self, _: Coords, _: &Chunk) {
+    fn write_slot(file: &mut File, index: usize, slot: Slot) -> io::Result<()> {
+        let mut entry = [0u8; HEADER_ENTRY_SIZE];
+        entry[0 .. 4].copy_from_slice(&slot.offset.to_le_bytes());
+        entry[4 .. 8].copy_from_slice(&slot.length.to_le_bytes());
+        file.seek(SeekFrom::Start((index * HEADER_ENTRY_SIZE) as u64))?;
+        file.write_all(&entry)
     }
 }
 
+/// Run-length encodes a chunk as (run length, block ID, block state) byte
+/// triples
+fn encode(chunk: &Chunk) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut run: Option<(Block, u8)> = None;
+
+    for block in chunk.iter() {
+        run = match run {
+            Some((prev, n)) if prev == block && n < u8::MAX => Some((prev, n + 1)),
+            Some((prev, n)) => {
+                bytes.extend_from_slice(&[n, prev.id(), prev.state()]);
+                Some((block, 1))
+            }
+            None => Some((block, 1)),
+        };
+    }
+
+    if let Some((prev, n)) = run {
+        bytes.extend_from_slice(&[n, prev.id(), prev.state()]);
+    }
+
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Option<Chunk> {
+    if bytes.len() % 3 != 0 {
+        return None;
+    }
+
+    let mut blocks = Vec::with_capacity(crate::chunk::VOLUME as usize);
+    for run in bytes.chunks_exact(3) {
+        let block = Block::from_parts(run[1], run[2]);
+        blocks.extend(std::iter::repeat(block).take(run[0] as usize));
+    }
+
+    if blocks.len() != crate::chunk::VOLUME as usize {
+        return None;
+    }
+
+    let packed: Packed = blocks.into_iter().collect();
+    Some(packed.into())
+}
+
+/// A `ChunkStore` keeping chunks in region files under a world directory
+///
+/// Each region file holds an 8x8x8 group of chunks. It begins with a table of
+/// (offset, length) entries, one per chunk, followed by the encoded chunks.
+/// Rewritten chunks reuse their old space when they fit, and are appended
+/// otherwise.
+pub struct Regions {
+    dir: PathBuf,
+}
+
+impl Regions {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Regions> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Regions { dir })
+    }
+
+    fn region_path(&self, region: RegionCoords) -> PathBuf {
+        self.dir.join(region.file_name())
+    }
+
+    fn try_load(&mut self, coords: Coords) -> io::Result<Option<Chunk>> {
+        let path = self.region_path(RegionCoords::containing(coords));
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => { return Ok(None); }
+            Err(e) => { return Err(e); }
+        };
+
+        let slot = Header::read(&mut file)?.0[slot_index(coords)];
+        if slot.is_vacant() {
+            return Ok(None);
+        }
+
+        let mut bytes = vec![0u8; slot.length as usize];
+        file.seek(SeekFrom::Start(slot.offset as u64))?;
+        file.read_exact(&mut bytes)?;
+
+        decode(&bytes)
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed chunk"))
+    }
+
+    fn try_store(&mut self, coords: Coords, chunk: &Chunk) -> io::Result<()> {
+        let path = self.region_path(RegionCoords::containing(coords));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)?;
+
+        if file.metadata()?.len() < HEADER_SIZE as u64 {
+            file.set_len(HEADER_SIZE as u64)?;
+        }
+
+        let index = slot_index(coords);
+        let old_slot = Header::read(&mut file)?.0[index];
+
+        let bytes = encode(chunk);
+        let length = bytes.len() as u32;
+
+        let offset = if !old_slot.is_vacant() && length <= old_slot.length {
+            old_slot.offset
+        }
+        else {
+            file.seek(SeekFrom::End(0))? as u32
+        };
+
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(&bytes)?;
+        Header::write_slot(&mut file, index, Slot { offset, length })
+    }
+}
+
+impl ChunkStore for Regions {
+    fn load(&mut self, coords: Coords) -> Option<Chunk> {
+        self.try_load(coords)
+            .unwrap_or_else(|e| {
+                eprintln!("error loading chunk {:?}: {}", coords.unwrap(), e);
+                None
+            })
+    }
+
+    fn store(&mut self, coords: Coords, chunk: &Chunk) {
+        if let Err(e) = self.try_store(coords, chunk) {
+            eprintln!("error storing chunk {:?}: {}", coords.unwrap(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            block::stock::*,
+            chunk::Array,
+            mesher::Direction,
+        },
+    };
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir()
+                .join(format!("rk-voxel-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn coords(x: i32, y: i32, z: i32) -> Coords {
+        Coords::new(P3::new(x, y, z))
+    }
+
+    fn patterned(seed: usize) -> Chunk {
+        Array::generate(|ijk| {
+            match (ijk.x * 7 + ijk.y * 3 + ijk.z + seed) % 5 {
+                0 => STONE,
+                1 => SOIL,
+                2 => GRASS,
+                3 => TREE_TRUNK.with_facing(Direction::YIn).with_variant(2),
+                _ => Block::EMPTY,
+            }
+        }).into()
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::new("round-trip");
+        let mut store = Regions::open(&dir.0).unwrap();
+
+        let all = [
+            (coords( 0,  0,  0), patterned(0)),
+            (coords( 1,  0,  0), Array::new_filled(STONE).into()),
+            (coords(-1, -9,  3), patterned(1)),
+            (coords( 7,  7, -8), patterned(2)),
+        ];
+
+        for (coords, chunk) in &all {
+            store.store(*coords, chunk);
+        }
+
+        let mut store = Regions::open(&dir.0).unwrap();
+        for (coords, chunk) in &all {
+            let loaded = store.load(*coords).unwrap();
+            assert!(*chunk == loaded);
+        }
+
+        assert!(store.load(coords(2, 0, 0)).is_none());
+        assert!(store.load(coords(100, 100, 100)).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        let bytes = encode(&patterned(5));
+        assert!(decode(&bytes).unwrap() == patterned(5));
+
+        // cut mid-run, or a whole run short of the chunk's volume
+        assert!(decode(&bytes[.. bytes.len() - 1]).is_none());
+        assert!(decode(&bytes[.. bytes.len() - 3]).is_none());
+    }
+
+    #[test]
+    fn rewrite() {
+        let dir = TempDir::new("rewrite");
+        let mut store = Regions::open(&dir.0).unwrap();
+        let at = coords(3, 2, 1);
+        let neighbour = coords(3, 2, 2);
+
+        store.store(at, &Array::new_filled(Block::EMPTY).into());
+        store.store(neighbour, &patterned(3));
+
+        // grows, so must move to the end of the file
+        store.store(at, &patterned(4));
+        assert!(store.load(at).unwrap() == patterned(4));
+        assert!(store.load(neighbour).unwrap() == patterned(3));
+
+        // shrinks, so is rewritten in place
+        store.store(at, &Array::new_filled(GRASS).into());
+        assert!(store.load(at).unwrap() == Chunk::from(Array::new_filled(GRASS)));
+        assert!(store.load(neighbour).unwrap() == patterned(3));
+    }
+}
@@ -0,0 +1,266 @@
+
+use {
+    crate::math::*,
+    std::{
+        fmt,
+        fs,
+        io,
+        path::Path,
+    },
+};
+
+/// Real seconds in a day of the game
+pub const DAY_LENGTH: f32 = 20. * 60.;
+
+/// Times of day, as fractions of a day from midnight
+pub const DAWN: f32 = 0.25;
+pub const NOON: f32 = 0.5;
+
+/// How far the sun's path leans away from overhead, so that noon light
+/// doesn't fall straight down and leave every side face alike
+const SUN_TILT: f32 = 0.4;
+
+/// How much of the daylight is left at night, by the moon
+const MOONLIGHT: f32 = 0.15;
+
+const DAY_ZENITH:    [f32; 3] = [0.25, 0.45, 0.9];
+const DAY_HORIZON:   [f32; 3] = [0.65, 0.78, 0.95];
+const DUSK_HORIZON:  [f32; 3] = [0.9,  0.5,  0.3];
+const NIGHT_ZENITH:  [f32; 3] = [0.01, 0.01, 0.03];
+const NIGHT_HORIZON: [f32; 3] = [0.03, 0.03, 0.07];
+
+/// Something asked of the clock by the player
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Command {
+    /// Jumps to a time of day
+    Set(f32),
+    /// Jumps forward to the next of dawn, noon, dusk and midnight
+    NextQuarter,
+    /// Stops the clock, or starts it again
+    ToggleFrozen,
+}
+
+/// The time of day, which moves the sun and moon and lights the sky
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Clock {
+    /// The fraction of the day gone since midnight, from 0 up to 1
+    time:   f32,
+    frozen: bool,
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("bad clock: {}", what))
+}
+
+/// Wraps a time into the day; `rem_euclid` rounds times just short of a day
+/// up to 1, which is midnight again
+fn wrap(time: f32) -> f32 {
+    let time = time.rem_euclid(1.);
+    if time < 1. { time } else { 0. }
+}
+
+fn smoothstep(lo: f32, hi: f32, x: f32) -> f32 {
+    let t = ((x - lo) / (hi - lo)).max(0.).min(1.);
+    t * t * (3. - 2. * t)
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> V3 {
+    V3::from(a).lerp(&V3::from(b), t)
+}
+
+impl Clock {
+    pub fn new(time: f32) -> Clock {
+        Clock { time: wrap(time), frozen: false }
+    }
+
+    pub fn set(&mut self, time: f32) {
+        self.time = wrap(time);
+    }
+
+    pub fn tick(&mut self, dt: f32) {
+        if !self.frozen {
+            self.set(self.time + dt / DAY_LENGTH);
+        }
+    }
+
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::Set(time)    => self.set(time),
+            Command::NextQuarter  => self.set(((self.time * 4.).floor() + 1.) / 4.),
+            Command::ToggleFrozen => self.frozen = !self.frozen,
+        }
+    }
+
+    /// The unit vector toward the sun, which rises along +X, is highest at
+    /// noon and sets along −X
+    pub fn sun_direction(&self) -> V3 {
+        let angle = (self.time - DAWN) * 2. * PI;
+        V3::new(angle.cos(), SUN_TILT, angle.sin()).normalize()
+    }
+
+    /// The unit vector toward whichever of the sun and moon is up
+    pub fn light_direction(&self) -> V3 {
+        let sun = self.sun_direction();
+        if sun.z >= 0. { sun } else { -sun }
+    }
+
+    /// How brightly the sky lights the world, from 0 to 1, easing through
+    /// dawn and dusk rather than switching as the sun crosses the horizon
+    pub fn daylight(&self) -> f32 {
+        MOONLIGHT + (1. - MOONLIGHT) * self.day()
+    }
+
+    /// How far the sky has brightened from night toward day, from 0 to 1
+    fn day(&self) -> f32 {
+        smoothstep(-0.15, 0.25, self.sun_direction().z)
+    }
+
+    /// The colour of the sky straight overhead
+    pub fn zenith_color(&self) -> V3 {
+        mix(NIGHT_ZENITH, DAY_ZENITH, self.day())
+    }
+
+    /// The colour of the sky along the horizon, reddened near sunrise and
+    /// sunset, which distant things fade into
+    pub fn horizon_color(&self) -> V3 {
+        let glow = 1. - smoothstep(0., 0.3, self.sun_direction().z.abs());
+        mix(NIGHT_HORIZON, DAY_HORIZON, self.day()).lerp(&V3::from(DUSK_HORIZON), 0.6 * glow)
+    }
+
+    /// Reads a clock as written by `save`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Clock> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Writes the time and whether the clock is frozen as a small text file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, format!("time   = {}\nfrozen = {}\n", self.time, self.frozen))
+    }
+}
+
+impl fmt::Display for Clock {
+    /// The time as on a 24-hour clock
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = (self.time * 24. * 60.) as u32;
+        write!(f, "{:02}:{:02}", minutes / 60, minutes % 60)?;
+        if self.frozen {
+            write!(f, " (frozen)")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Clock {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Clock> {
+        let mut time = None;
+        let mut frozen = false;
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut parts = line.splitn(2, '=').map(str::trim);
+            match (parts.next(), parts.next()) {
+                (Some("time"), Some(value)) => {
+                    let value: f32 = value.parse().map_err(|_| invalid("time"))?;
+                    if !(0. .. 1.).contains(&value) {
+                        return Err(invalid("time"));
+                    }
+                    time = Some(value);
+                }
+                (Some("frozen"), Some(value)) => {
+                    frozen = value.parse().map_err(|_| invalid("frozen"))?;
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        let time = time.ok_or_else(|| invalid("no time"))?;
+        Ok(Clock { time, frozen })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIDNIGHT: f32 = 0.;
+    const DUSK:     f32 = 0.75;
+
+    #[test]
+    fn sun_crosses_the_sky() {
+        let at = |time| Clock::new(time).sun_direction();
+        assert!(at(DAWN).z.abs() < 1e-5);
+        assert!(at(DAWN).x > 0.);
+        assert!(at(NOON).z > 0.9);
+        assert!(at(DUSK).z.abs() < 1e-5);
+        assert!(at(DUSK).x < 0.);
+        assert!(at(MIDNIGHT).z < -0.9);
+
+        // the moon takes over at night
+        assert!(Clock::new(MIDNIGHT).light_direction().z > 0.9);
+    }
+
+    #[test]
+    fn days_are_brighter_than_nights() {
+        let noon = Clock::new(NOON);
+        let midnight = Clock::new(MIDNIGHT);
+        assert_eq!(noon.daylight(), 1.);
+        assert_eq!(midnight.daylight(), MOONLIGHT);
+
+        let morning: Vec<f32> = (0 ..= 10)
+            .map(|i| Clock::new(MIDNIGHT + i as f32 * 0.05).daylight())
+            .collect();
+        assert!(morning.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        assert!(noon.zenith_color().z > midnight.zenith_color().z);
+        assert!(noon.horizon_color().z > midnight.horizon_color().z);
+        assert!(noon.horizon_color().x > noon.zenith_color().x);
+        let dusk = Clock::new(DUSK).horizon_color();
+        assert!(dusk.x > dusk.z);
+    }
+
+    #[test]
+    fn ticks_and_commands() {
+        let mut clock = Clock::new(DUSK);
+        clock.tick(DAY_LENGTH / 2.);
+        assert!((clock.time - DAWN).abs() < 1e-5);
+
+        clock.apply(Command::ToggleFrozen);
+        clock.tick(DAY_LENGTH / 4.);
+        assert!((clock.time - DAWN).abs() < 1e-5);
+        clock.apply(Command::ToggleFrozen);
+        assert!(!clock.frozen);
+        assert_eq!(clock.to_string(), "06:00");
+
+        clock.apply(Command::Set(NOON));
+        clock.apply(Command::NextQuarter);
+        assert_eq!(clock.time, DUSK);
+        clock.apply(Command::NextQuarter);
+        assert_eq!(clock.time, MIDNIGHT);
+        clock.apply(Command::Set(-0.25));
+        assert_eq!(clock.time, DUSK);
+
+        // just short of midnight rounds to it, rather than to the end of the day
+        clock.apply(Command::Set(-1e-9));
+        assert_eq!(clock.time, MIDNIGHT);
+        assert_eq!(clock.to_string(), "00:00");
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut clock = Clock::new(0.3125);
+        clock.apply(Command::ToggleFrozen);
+        assert_eq!(clock.to_string(), "07:30 (frozen)");
+
+        let path = std::env::temp_dir().join(format!("rk-voxel-test-{}.clock", std::process::id()));
+        clock.save(&path).unwrap();
+        let loaded = Clock::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), clock);
+
+        let rejected = |text: &str| text.parse::<Clock>().unwrap_err().kind() == io::ErrorKind::InvalidData;
+        assert!(rejected(""));
+        assert!(rejected("time = 1.5"));
+        assert!(rejected("time = 0.5\nfrozen = maybe"));
+        assert!(rejected("time = 0.5\nspeed = 2"));
+    }
+}
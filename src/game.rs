@@ -1,49 +1,190 @@
 
 use {
     crate::{
-        block::{self, Block},
+        block::{Block, BlockDef, Orient, Registry},
         chunk::{self, Chunk, BlockCoords, Coords as ChunkCoords},
+        chunk_cache,
         chunk_maker,
         chunk_source,
         chunk_store,
+        clock::{self, Clock},
         gl,
+        journal::{Change, Journal},
+        light,
         math::*,
-        mesher,
+        mesher::{self, Direction},
+        schematic,
         shader,
+        sky::Sky,
         stage,
         texture::TextureAtlas,
+        workers,
+        world_edit::{self, Operation, Shape, Tool},
+    },
+    std::{
+        collections::VecDeque,
+        fmt,
+        io,
+        path::Path,
+        rc::Rc,
+        sync::Arc,
+        time::{Duration, Instant},
     },
-    std::rc::Rc,
 };
 
 const STAGE_RADIUS: i32 = 10;
 
+/// View depths over which distant blocks fade into the sky, ending short of
+/// the stage's edge so that chunks are hidden as they come and go
+const FOG_END:   f32 = ((STAGE_RADIUS - 1) * chunk::DIM) as f32;
+const FOG_START: f32 = FOG_END * 0.5;
+
+/// Radius of the sphere and cylinder brushes, in blocks
+const BRUSH_RADIUS: f32 = 3.;
+const BRUSH_HEIGHT: f32 = 6.;
+/// The largest region a world-edit tool may work over, in blocks
+const MAX_EDIT_VOLUME: usize = 1 << 20;
+
+/// Where the clipboard is saved to and loaded from, outside any one world
+const SCHEMATIC_FILE: &str = "clipboard.schematic";
+
+/// Memory set aside for chunks which have left the stage, in bytes
+const CHUNK_CACHE_BUDGET: usize = 128 << 20;
+
+const WORLD_DIR: &str = "world";
+
+const BLOCKS_FILE: &str = "blocks.txt";
+
+/// Where the time of day is kept, within the world directory
+const CLOCK_FILE: &str = "clock.txt";
+
 const FOV: f32 = 90.;
 const ZOOM_FACTOR: f32 = 5.;
 
 const SPRINT_FACTOR: f32 = 2.;
 const PLAYER_SPEED: f32 = 5.;
 
+/// Downward acceleration while walking, in blocks per second squared
+const GRAVITY: f32 = 25.;
+/// Upward speed given by a jump, enough to clear about one block
+const JUMP_SPEED: f32 = 8.;
+const TERMINAL_VELOCITY: f32 = 50.;
+
+/// A little over one block, so that single-block ledges are walked up
+const STEP_HEIGHT: f32 = 1.1;
+
 const EDIT_INTERVAL: f32 = 0.1;
 
+const SYNC_INTERVAL: f32 = 30.;
+
+/// Edit transactions kept for undoing
+const JOURNAL_LIMIT: usize = 256;
+
+/// Texels of opaque and cutout faces less opaque than this aren't drawn
+const OPAQUE_ALPHA_CUTOFF: f32 = 0.5;
+
+/// Time per frame which may be spent uploading finished meshes
+const MESH_UPLOAD_BUDGET: Duration = Duration::from_millis(4);
+
+/// Time per frame which may be spent staging and lighting arrived chunks
+const CHUNK_LIGHTING_BUDGET: Duration = Duration::from_millis(4);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MeshState {
+    /// The mesh, if any, no longer matches the blocks
+    Stale,
+    /// A meshing job with this id is under way
+    Pending(u64),
+    Current,
+}
+
 #[derive(Clone)]
 struct StageChunk {
-    chunk: Chunk,
-    mesh:  Option<Rc<dyn mesher::Mesh>>,
+    chunk:      Chunk,
+    /// Worked out afresh whenever the chunk is staged, rather than stored
+    light:      light::Levels,
+    /// The last mesh made, kept for drawing until its replacement is ready
+    mesh:       Option<Rc<dyn mesher::Mesh>>,
+    mesh_state: MeshState,
 }
 
 impl StageChunk {
     fn new(chunk: Chunk) -> StageChunk {
-        StageChunk { chunk, mesh: None }
+        StageChunk {
+            chunk,
+            light:      light::Levels::new_dark(),
+            mesh:       None,
+            mesh_state: MeshState::Stale,
+        }
     }
 }
 
 type Stage = stage::Stage<StageChunk>;
 
+/// The staged chunks as light sees them
+///
+/// Light spreads only through staged chunks, stopping at the stage's edge.
+/// Nothing is known of what lies above the stage, so the sky is taken to
+/// shine straight down onto the highest staged blocks.
+struct Lighting<'a> {
+    stage:   &'a mut Stage,
+    heights: &'a mut light::Heights,
+}
+
+impl light::World for Lighting<'_> {
+    fn block(&self, at: BlockCoords) -> Option<Block> {
+        let (coords, offset) = at.chunk_and_offset();
+        self.stage.at_absolute(coords).map(|stage_chunk| stage_chunk.chunk[offset])
+    }
+
+    fn packed_light(&self, at: BlockCoords) -> u8 {
+        let (coords, offset) = at.chunk_and_offset();
+        self.stage.at_absolute(coords).map_or(0, |stage_chunk| stage_chunk.light.get(offset))
+    }
+
+    fn set_packed_light(&mut self, at: BlockCoords, packed: u8) {
+        let (coords, offset) = at.chunk_and_offset();
+        if let Some(stage_chunk) = self.stage.at_absolute_mut(coords) {
+            if stage_chunk.light.get(offset) != packed {
+                stage_chunk.light.set(offset, packed);
+                invalidate_meshes_around(self.stage, at);
+            }
+        }
+    }
+
+    fn heights(&self) -> &light::Heights {
+        self.heights
+    }
+
+    fn heights_mut(&mut self) -> &mut light::Heights {
+        self.heights
+    }
+
+    fn bottom(&self) -> i32 {
+        let mins = self.stage.relative_to_absolute(self.stage.relative_mins());
+        mins.block_mins().unwrap().z
+    }
+}
+
+/// Marks stale the mesh of every chunk whose meshing buffer includes `block`
+fn invalidate_meshes_around(stage: &mut Stage, block: BlockCoords) {
+    let (coords, offset) = block.chunk_and_offset();
+
+    let lo = offset.map(|o| if o == 0 { -1 } else { 0 });
+    let hi = offset.map(|o| if o as i32 == chunk::DIM - 1 { 2 } else { 1 });
+
+    for step in SpaceIter::new(lo, hi) {
+        if let Some(chunk) = stage.at_absolute_mut(coords + step) {
+            chunk.mesh_state = MeshState::Stale;
+        }
+    }
+}
+
 mod meshing_buffer {
     use crate::{array3d, chunk, block::Block};
 
-    const DIM: usize = chunk::DIM as usize + 1;
+    /// A chunk plus a one-block border on every side
+    const DIM: usize = chunk::DIM as usize + 2;
 
     #[derive(Default, Clone)]
     pub struct Dims;
@@ -55,34 +196,67 @@ mod meshing_buffer {
     }
 
     pub type Buffer = array3d::ArrayOwned<Block, Dims>;
+    pub type Light  = array3d::ArrayOwned<u8, Dims>;
 }
 
-use meshing_buffer::Buffer as MeshingBuffer;
+use meshing_buffer::{Buffer as MeshingBuffer, Light as MeshingLight};
+
+/// Whether a chunk and the neighbours contributing to its mesh are each
+/// filled with one kind of block, with no faces showing between them
+fn is_featureless(stage: &Stage, registry: &Registry, rel: V3i32) -> bool {
+    let uniform = |rel| {
+        stage.at_relative(rel)
+            .and_then(|sc: &StageChunk| sc.chunk.uniform())
+    };
+
+    let inner = match uniform(rel) {
+        Some(block) => block,
+        None        => { return false; }
+    };
+
+    // blocks which aren't cubes always have faces of their own, while faces
+    // between cubes of one kind never show
+    let is_cube = inner.is_empty() || registry.get(inner).model.is_cube();
+    is_cube && !mesher::face_shows(registry, inner, inner)
+        && [V3::x(), V3::y(), V3::z()].iter()
+            .all(|step| match uniform(rel + step) {
+                Some(beyond) => {
+                    !mesher::face_shows(registry, inner, beyond) &&
+                    !mesher::face_shows(registry, beyond, inner)
+                }
+                None => false,
+            })
+}
 
-fn fill_meshing_buffer(buffer: &mut MeshingBuffer, stage: &Stage, rel: V3i32)
+fn fill_meshing_buffer(
+    buffer: &mut MeshingBuffer,
+    light:  &mut MeshingLight,
+    stage:  &Stage,
+    rel:    V3i32,
+)
     -> Option<()>
 {
     const CDIM: usize = chunk::DIM as usize;
 
-    let zero = V3::zeros();
-    let dims = V3::repeat(CDIM);
-
-    let c0 = &stage.at_relative(rel)?.chunk;
-    let cx = &stage.at_relative(rel + V3::x())?.chunk;
-    let cy = &stage.at_relative(rel + V3::y())?.chunk;
-    let cz = &stage.at_relative(rel + V3::z())?.chunk;
-
-    buffer.slice_mut(zero, dims)
-        .copy_from(&c0.slice(zero, dims));
+    // for each neighbour direction along an axis: where to read from in the
+    // neighbour, where to write to in the buffer, and how many layers
+    let span = |step: i32| match step {
+        -1 => (CDIM - 1, 0,        1),
+         0 => (0,        1,        CDIM),
+         _ => (0,        CDIM + 1, 1),
+    };
 
-    buffer.slice_mut(V3::new(CDIM, 0, 0), V3::new(1, CDIM, CDIM))
-        .copy_from(&cx.slice(zero, V3::new(1, CDIM, CDIM)));
+    for step in SpaceIter::new(V3::repeat(-1), V3::repeat(2)) {
+        let stage_chunk = stage.at_relative(rel + step)?;
 
-    buffer.slice_mut(V3::new(0, CDIM, 0), V3::new(CDIM, 1, CDIM))
-        .copy_from(&cy.slice(zero, V3::new(CDIM, 1, CDIM)));
+        let spans = step.map(span);
+        let src  = spans.map(|(src, _, _)| src);
+        let dst  = spans.map(|(_, dst, _)| dst);
+        let dims = spans.map(|(_, _, dims)| dims);
 
-    buffer.slice_mut(V3::new(0, 0, CDIM), V3::new(CDIM, CDIM, 1))
-        .copy_from(&cz.slice(zero, V3::new(CDIM, CDIM, 1)));
+        stage_chunk.chunk.copy_into(src, &mut buffer.slice_mut(dst, dims));
+        stage_chunk.light.copy_into(src, &mut light.slice_mut(dst, dims));
+    }
 
     Some(())
 }
@@ -96,12 +270,29 @@ pub struct Inputs {
     pub up:    bool,
     pub down:  bool,
     pub fast:  bool,
+    /// Held to switch between walking and flying
+    pub fly:   bool,
 
     pub cam_delta: V2,
     pub zoom:  bool,
 
     pub build: bool,
     pub smash: bool,
+    /// Held to copy the targeted block into the hand
+    pub pick:  bool,
+    pub undo:  bool,
+    pub redo:  bool,
+
+    /// A slot of the placeable blocks chosen since the last tick
+    pub select: Option<usize>,
+    /// Scroll wheel steps since the last tick
+    pub scroll: i32,
+    /// Steps through the held block's variants since the last tick
+    pub variant: i32,
+    /// A world-edit tool used since the last tick
+    pub tool:   Option<Tool>,
+    /// A change to the time of day asked for since the last tick
+    pub clock:  Option<clock::Command>,
 }
 
 impl Inputs {
@@ -114,25 +305,51 @@ impl Inputs {
             up:    false,
             down:  false,
             fast:  false,
+            fly:   false,
 
             cam_delta: V2::zeros(),
             zoom:      false,
 
             build: false,
             smash: false,
+            pick:  false,
+            undo:  false,
+            redo:  false,
+
+            select: None,
+            scroll:  0,
+            variant: 0,
+            tool:    None,
+            clock:   None,
         }
     }
 
     pub fn take(&mut self) -> Inputs {
         let out = *self;
         self.cam_delta = V2::zeros();
+        self.select = None;
+        self.scroll = 0;
+        self.variant = 0;
+        self.tool = None;
+        self.clock = None;
         out
     }
 }
 
-type MesherFn = dyn for<'a> FnMut(block::Slice<'a>) -> Rc<dyn mesher::Mesh>;
+struct MeshJob {
+    coords: ChunkCoords,
+    id:     u64,
+    buffer: Box<MeshingBuffer>,
+    light:  Box<MeshingLight>,
+}
+
+struct MeshResult {
+    coords: ChunkCoords,
+    id:     u64,
+    quads:  mesher::Quads,
+}
 
-type ChunkSource = chunk_source::Source<chunk_store::Null, chunk_maker::Test>;
+type ChunkSource = chunk_source::Source<chunk_store::Regions, chunk_maker::Test>;
 
 
 struct Facing {
@@ -183,77 +400,133 @@ impl Facing {
     }
 }
 
+/// A block struck by `raycast`
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct BlockHit {
+    block:    BlockCoords,
+    /// Normal of the face struck, toward the side the segment came from
+    normal:   V3i32,
+    /// How far along the segment the face was struck
+    distance: f32,
+}
+
+/// Finds the first block along a segment whose model it strikes, not
+/// counting the one it starts in, stepping through the blocks it passes
+///
+/// Stops short at the first block `block_at` can't give.
+fn raycast(segment: Segment, registry: &Registry, block_at: impl Fn(BlockCoords) -> Option<Block>)
+    -> Option<BlockHit>
+{
+    let length = segment.stride().norm();
+
+    for crossing in Traversal::new(segment).skip(1) {
+        let block = BlockCoords::new(crossing.cell.into());
+        let value = block_at(block)?;
+
+        if value.is_nonempty() {
+            let def = registry.get(value);
+            let mins = P3::from(block.unwrap_f32());
+            let nearest = def.model.boxes(def.orient, value.facing()).into_iter()
+                .filter_map(|model_box| model_box.in_world(mins).intersect(&segment))
+                .min_by_key(|hit| OrdFloat(hit.lambda));
+
+            if let Some(hit) = nearest {
+                return Some(BlockHit {
+                    block,
+                    normal:   hit.normal.map(|x| x as i32),
+                    distance: hit.lambda * length,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Counts from the most recent frame
+#[derive(Clone, Copy, Default)]
+pub struct DrawStats {
+    pub drawn:  usize,
+    pub culled: usize,
+}
+
+impl fmt::Display for DrawStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} chunks drawn, {} culled", self.drawn, self.culled)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Movement {
+    Walking,
+    Flying,
+}
+
 pub struct Game {
+    registry: Arc<Registry>,
     source:   ChunkSource,
     stage:    Stage,
-    mesher:   Box<MesherFn>,
-    mesh_buf: MeshingBuffer,
+    heights:  light::Heights,
+    shader:   shader::Program,
+    sky:      Sky,
     atlas:    TextureAtlas,
+    clock:    Clock,
+
+    mesh_workers: workers::Pool<MeshJob, MeshResult>,
+    next_mesh_id: u64,
+    meshes_ready: VecDeque<MeshResult>,
+    draw_stats:   DrawStats,
 
     player_position: P3,
+    player_velocity: V3,
     player_facing:   Facing,
+    movement:        Movement,
+    on_ground:       bool,
+    fly_held:        bool,
     zoom:            bool,
 
     selected_block: BlockCoords,
+    /// How far the eye is from the face of the selected block, if one is hit
+    hit_distance:   Option<f32>,
+    /// Where a block placed now would go, beside the selected one
+    build_block:    BlockCoords,
+    held_block:     Block,
+    pick_held:      bool,
+    selection:      world_edit::Selection,
+    clipboard:      Option<world_edit::Clipboard>,
+    journal:        Journal,
+    undo_held:      bool,
+    redo_held:      bool,
     edit_timer:     f32,
-}
-
-fn chunk_clip(
-    coords: ChunkCoords,
-    chunk:  &Chunk,
-    hitbox: Option<Box3>,
-    motion: Segment)
-    -> Option<(BlockCoords, box3::Intersection)>
-{
-    let chunk_pos = coords.block_mins().unwrap_f32().into();
-
-    // TODO this works, but stinks
-    let chunk_box = {
-        let b = Box3::with_dims(chunk_pos, V3::repeat(chunk::DIM as f32));
-        if let Some(hitbox) = &hitbox { b.dilate(hitbox) }
-        else                          { b }
-    };
-
-    // TODO simplify check when source-inside-box intersections work
-    if !chunk_box.contains(motion.source()) && chunk_box.intersect(&motion).is_none() {
-        return None;
-    }
-
-    let block_box = {
-        let b = Box3::with_dims(chunk_pos, V3::repeat(1.));
-        if let Some(hitbox) = &hitbox { b.dilate(hitbox) }
-        else                          { b }
-    };
-
-    chunk.indexed_iter()
-        .filter_map(|(ijk, block)| {
-            if *block == Block::Empty { return None; }
-            block_box.at(ijk.map(|x| x as f32).into())
-                .intersect(&motion)
-                .map(|ixn| (coords.block_at_offset(ijk.map(|x| x as u8)), ixn))
-        })
-        .min_by_key(|(_, ixn)| OrdFloat(ixn.lambda))
+    sync_timer:     f32,
 }
 
 impl Game {
     pub fn new() -> Result<Game, Box<dyn std::error::Error>> {
-        let source = ChunkSource::new(
-            chunk_store::Null::new(),
-            chunk_maker::Test::new(12345)
+        let registry = Arc::new(Registry::load(BLOCKS_FILE)?);
+
+        let source = ChunkSource::with_cache_capacity(
+            chunk_store::Regions::open(WORLD_DIR)?,
+            chunk_maker::Test::new(12345, &registry)?,
+            CHUNK_CACHE_BUDGET
         );
 
         let stage = Stage::new(STAGE_RADIUS, ChunkCoords::origin());
 
-        let mesher: Box<MesherFn> = {
+        let mesh_workers = {
             use mesher::Mesher;
-            let mesher = mesher::Simple::new();
-        //  let mut builder = mesher::NaiveTriangleMeshBuilder::new();
-            let mut builder = mesher::InstancedQuadMeshBuilder::new();
-            Box::new(move |buffer| mesher.make_mesh(buffer, &mut builder))
+            let mesher = mesher::Greedy::new(registry.clone());
+            workers::Pool::new(
+                "mesher",
+                workers::Pool::<MeshJob, MeshResult>::default_thread_count(),
+                move |job: MeshJob| {
+                    let mut quads = mesher::Quads::new();
+                    mesher.make_mesh(job.buffer.whole_slice(), job.light.whole_slice(), &mut quads);
+                    MeshResult { coords: job.coords, id: job.id, quads }
+                }
+            )
         };
 
-        let mesh_buf = MeshingBuffer::new_filled(Block::Empty);
-
         let shader = {
         //  static V_SHADER_SRC: &'static str = include_str!("shader/test-v.glsl");
             static V_SHADER_SRC: &'static str = include_str!("shader/instanced-quad-vert.glsl");
@@ -263,31 +536,65 @@ impl Game {
             shader::link(&[v_shader, f_shader])?
         };
 
+        let sky = Sky::new()?;
+
         let atlas = TextureAtlas::load("atlas.png", V2::new(16, 16), 5)?;
 
         unsafe {
             shader.bind();
-            gl::ClearColor(0.4, 0.6, 1.0, 1.0);
             gl::Enable(gl::CULL_FACE);
             gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::Enable(gl::BLEND);
             gl::Enable(gl::DEPTH_TEST);
         }
 
+        let held_block = registry.cycle(Block::EMPTY, 0);
+
+        let clock_path = Path::new(WORLD_DIR).join(CLOCK_FILE);
+        let clock = match Clock::load(&clock_path) {
+            Ok(clock) => clock,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    eprintln!("failed to load {}: {}", clock_path.display(), err);
+                }
+                Clock::new(clock::DAWN + 0.05)
+            }
+        };
+
         let game = Game {
+            registry,
             source,
             stage,
-            mesher,
-            mesh_buf,
-        //  shader,
+            heights: light::Heights::new(),
+            shader,
+            sky,
             atlas,
+            clock,
+
+            mesh_workers,
+            next_mesh_id: 0,
+            meshes_ready: VecDeque::new(),
+            draw_stats:   DrawStats::default(),
 
             player_position: P3::new(0., 0., 30.),
+            player_velocity: V3::zeros(),
             player_facing:   Facing::new(),
+            movement:        Movement::Flying,
+            on_ground:       false,
+            fly_held:        false,
             zoom:            false,
 
             selected_block: BlockCoords::origin(),
+            build_block:    BlockCoords::origin(),
+            hit_distance:   None,
+            held_block,
+            pick_held:      false,
+            selection:      world_edit::Selection::new(),
+            clipboard:      None,
+            journal:        Journal::new(JOURNAL_LIMIT),
+            undo_held:      false,
+            redo_held:      false,
             edit_timer:     EDIT_INTERVAL,
+            sync_timer:     SYNC_INTERVAL,
         };
 
         Ok(game)
@@ -302,34 +609,86 @@ impl Game {
     }
 
     fn update_chunks(&mut self) {
-        let stale_chunks = self.stage.relocate(self.player_chunk_coords());
-        if !stale_chunks.is_empty() {
-            //eprintln!("loading {} stale chunks...", stale_chunks.len());
+        // made chunks go into the source's cache, and are picked up below as
+        // the stage still reports them missing
+        self.source.poll();
 
-            const MAX_LOAD: usize = 10;
-            let mut load_count = 0;
+        // chunks left over once the budget is spent stay missing, and are
+        // requested again next frame
+        let start = Instant::now();
+        let stale_chunks = self.stage.relocate(self.player_chunk_coords());
+        for stale_chunk in stale_chunks {
+            use stage::StaleChunk::*;
+            let coords = match stale_chunk {
+                Missing(coords) => { coords }
+
+                Evicted { old_coords, new_coords, value } => {
+                    self.source.store(old_coords, value.chunk);
+                    new_coords
+                }
+            };
 
-            for stale_chunk in stale_chunks {
-                use stage::StaleChunk::*;
-                let coords = match stale_chunk {
-                    Missing(coords) => { coords }
+            if start.elapsed() >= CHUNK_LIGHTING_BUDGET {
+                continue;
+            }
 
-                    Evicted { old_coords, new_coords, value } => {
-                        self.source.store(old_coords, value.chunk);
-                        new_coords
-                    }
-                };
+            if let Some((chunk, _)) = self.source.request(coords) {
+                let survey = light::Survey::new(&self.registry, &chunk);
+                self.stage.insert_absolute(coords, StageChunk::new(chunk));
 
-                if load_count < MAX_LOAD {
-                    let (chunk, from) = self.source.load(coords);
-                    let stage_chunk = StageChunk::new(chunk);
-                    self.stage.insert_absolute(coords, stage_chunk);
-                    if from == chunk_source::LoadedFrom::Maker {
-                        load_count += 1;
-                    }
+                let mut lighting = Lighting { stage: &mut self.stage, heights: &mut self.heights };
+                light::light_chunk(&mut lighting, &self.registry, coords, &survey);
+                if let Some(stage_chunk) = self.stage.at_absolute_mut(coords) {
+                    stage_chunk.light.compact();
                 }
             }
         }
+
+        // heights are kept while any chunk of their column is staged
+        let (stage, center) = (&self.stage, self.player_chunk_coords());
+        self.heights.retain(|column| stage.covers(ChunkCoords::new(column.push(center.unwrap().z).into())));
+
+        let stage = &self.stage;
+        self.source.cancel_requests(|coords| !stage.covers(coords));
+    }
+
+    /// Writes every modified chunk, staged or cached, to the chunk store
+    pub fn save(&mut self) {
+        let staged: Vec<ChunkCoords> = self.stage.absolute_coords_iter().collect();
+        for coords in staged {
+            if let Some(stage_chunk) = self.stage.at_absolute_mut(coords) {
+                self.source.sync_borrowed(coords, &mut stage_chunk.chunk);
+            }
+        }
+
+        self.source.sync();
+
+        let clock_path = Path::new(WORLD_DIR).join(CLOCK_FILE);
+        if let Err(err) = self.clock.save(&clock_path) {
+            eprintln!("failed to save {}: {}", clock_path.display(), err);
+        }
+    }
+
+    /// Saves everything and releases cached chunks, ready for exit
+    pub fn shut_down(&mut self) {
+        self.save();
+        self.source.flush();
+    }
+
+    /// Turns a block being placed against a face with the given normal, or
+    /// in mid-air, according to how its kind is oriented
+    fn orient(&self, block: Block, against: Option<V3i32>) -> Block {
+        let look = self.player_facing.direction();
+        let facing = match self.registry.get(block).orient {
+            Orient::Fixed      => None,
+            Orient::Axis       => match against {
+                Some(normal) => Direction::from_normal(normal.map(i32::abs)),
+                None         => Direction::nearest(look.map(f32::abs)),
+            },
+            Orient::Facing     => Direction::nearest(-look),
+            Orient::Horizontal => Direction::nearest(V3::new(-look.x, -look.y, 0.)),
+        };
+        block.with_facing(facing.unwrap_or(Direction::ZOut))
     }
 
     pub fn edit_blocks(&mut self, inputs: &Inputs, dt: f32) {
@@ -338,11 +697,15 @@ impl Game {
             self.player_facing.direction() * 4.
         );
 
-        let selection = self.world_clip(None, selection_beam);
+        let stage = &self.stage;
+        let selection = raycast(selection_beam, &self.registry, |block| {
+            let (coords, offset) = block.chunk_and_offset();
+            stage.at_absolute(coords).map(|stage_chunk| stage_chunk.chunk[offset])
+        });
+        self.hit_distance = selection.map(|hit| hit.distance);
 
-        let (kill_block, build_block) = if let Some((block, hit)) = selection {
-            let build_block = block + hit.normal.map(|x| x as i32);
-            (block, build_block)
+        let (kill_block, build_block) = if let Some(hit) = selection {
+            (hit.block, hit.block + hit.normal)
         }
         else {
             let block = BlockCoords::containing(selection_beam.destination());
@@ -350,6 +713,29 @@ impl Game {
         };
 
         self.selected_block = kill_block;
+        self.build_block = build_block;
+
+        if let Some(slot) = inputs.select {
+            if let Some(block) = self.registry.placeable().get(slot) {
+                self.held_block = *block;
+            }
+        }
+        self.held_block = self.registry.cycle(self.held_block, inputs.scroll);
+        if inputs.variant != 0 {
+            let variants = self.registry.get(self.held_block).variants as i32;
+            let variant = (self.held_block.variant() as i32 + inputs.variant).rem_euclid(variants);
+            self.held_block = self.held_block.with_variant(variant as u8);
+        }
+
+        if inputs.pick && !self.pick_held {
+            if let Some(hit) = selection {
+                let (coords, offset) = hit.block.chunk_and_offset();
+                if let Some(chunk) = self.stage.at_absolute(coords) {
+                    self.held_block = chunk.chunk[offset];
+                }
+            }
+        }
+        self.pick_held = inputs.pick;
 
         // TODO actual click detection
         if self.edit_timer > 0. {
@@ -361,7 +747,7 @@ impl Game {
             return;
         }
 
-        let (value, coords, offset) = if inputs.build {
+        let (value, block) = if inputs.build {
             let player_block = BlockCoords::containing(self.player_position);
             let offset = build_block - player_block;
             if offset.xy() == V2::zeros() && (0..=1).contains(&offset.z) {
@@ -369,104 +755,353 @@ impl Game {
                 return;
             }
 
-            let (coords, offset) = build_block.chunk_and_offset();
-            (Block::Stone, coords, offset)
+            let against = selection.map(|hit| hit.normal);
+            (self.orient(self.held_block, against), build_block)
         }
         else {
-            let (coords, offset) = kill_block.chunk_and_offset();
-            (Block::Empty, coords, offset)
+            (Block::EMPTY, kill_block)
+        };
+
+        self.journal.begin();
+        self.set_block(block, value);
+        self.journal.commit();
+
+        self.edit_timer = EDIT_INTERVAL;
+    }
+
+    /// Writes a block without journalling it, returning the block replaced
+    ///
+    /// Blocks in chunks which have left the stage are written through the
+    /// chunk source's cache or store. Chunks which haven't been made yet are
+    /// left alone, rather than made on the main thread, and give `None`.
+    fn write_block(&mut self, block: BlockCoords, value: Block) -> Option<Block> {
+        let (coords, offset) = block.chunk_and_offset();
+
+        let old = if let Some(stage_chunk) = self.stage.at_absolute_mut(coords) {
+            let old = stage_chunk.chunk[offset];
+            stage_chunk.chunk.set(offset, value);
+            let mut lighting = Lighting { stage: &mut self.stage, heights: &mut self.heights };
+            light::block_changed(&mut lighting, &self.registry, block);
+            old
+        }
+        else {
+            self.source.write_block(block, value)?
+        };
+
+        invalidate_meshes_around(&mut self.stage, block);
+        Some(old)
+    }
+
+    /// Reads a block from the stage, or from the chunk source's cache or
+    /// store if its chunk has left the stage
+    ///
+    /// Gives `None` for chunks which haven't been made yet.
+    fn block_at(&mut self, block: BlockCoords) -> Option<Block> {
+        let (coords, offset) = block.chunk_and_offset();
+        match self.stage.at_absolute(coords) {
+            Some(stage_chunk) => Some(stage_chunk.chunk[offset]),
+            None              => self.source.read_block(block),
+        }
+    }
+
+    /// Uses a world-edit tool on the selection, the clipboard or the
+    /// targeted block
+    fn use_tool(&mut self, tool: Tool) {
+        let target = self.selected_block;
+        let center = (target.unwrap_f32() + V3::repeat(0.5)).into();
+        let region = self.selection.bounds().map(Shape::Box);
+
+        let (shape, operation) = match tool {
+            Tool::MarkFirst | Tool::MarkSecond => {
+                let corner = if tool == Tool::MarkFirst { 0 } else { 1 };
+                self.selection.mark(corner, target);
+                eprintln!("selection corner {} at {:?}", corner + 1, target.unwrap());
+                return;
+            }
+
+            Tool::Copy | Tool::Paste | Tool::Rotate | Tool::Mirror |
+            Tool::SaveSchematic | Tool::LoadSchematic => {
+                self.use_clipboard(tool);
+                return;
+            }
+
+            Tool::Fill    => (region, Operation::Fill(self.held_block)),
+            Tool::Replace => {
+                let from = self.block_at(target).unwrap_or(Block::EMPTY);
+                (region, Operation::Replace { from, to: self.held_block })
+            }
+            Tool::Hollow  => (region, Operation::Hollow),
+
+            Tool::Sphere => (
+                Some(Shape::Sphere { center, radius: BRUSH_RADIUS }),
+                Operation::Fill(self.held_block)
+            ),
+            Tool::Cylinder => (
+                Some(Shape::Cylinder { base: center, radius: BRUSH_RADIUS, height: BRUSH_HEIGHT }),
+                Operation::Fill(self.held_block)
+            ),
         };
 
-        if let Some(chunk) = self.stage.at_absolute_mut(coords) {
-            chunk.chunk[offset] = value;
+        let shape = match shape {
+            Some(shape) => shape,
+            None => {
+                eprintln!("mark both corners of a selection first");
+                return;
+            }
+        };
 
-            // TODO proper mesh invalidation
-            chunk.mesh = None;
+        if shape.bounding_volume() > MAX_EDIT_VOLUME {
+            eprintln!("{:?} refused: more than {} blocks", tool, MAX_EDIT_VOLUME);
+            return;
+        }
 
-            if offset.x == 0 {
-                if let Some(chunk) = self.stage.at_absolute_mut(coords - V3::x()) {
-                    chunk.mesh = None;
+        let changes = world_edit::plan(&shape, operation, |block| {
+            self.block_at(block).unwrap_or(Block::EMPTY)
+        });
+        self.apply_edit(tool, &changes);
+    }
+
+    fn use_clipboard(&mut self, tool: Tool) {
+        if tool == Tool::Copy {
+            let bounds = match self.selection.bounds() {
+                Some(bounds) => bounds,
+                None => {
+                    eprintln!("mark both corners of a selection first");
+                    return;
                 }
+            };
+
+            if Shape::Box(bounds).bounding_volume() > MAX_EDIT_VOLUME {
+                eprintln!("{:?} refused: more than {} blocks", tool, MAX_EDIT_VOLUME);
+                return;
             }
 
-            if offset.y == 0 {
-                if let Some(chunk) = self.stage.at_absolute_mut(coords - V3::y()) {
-                    chunk.mesh = None;
+            let clipboard = world_edit::copy(&bounds, |block| {
+                self.block_at(block).unwrap_or(Block::EMPTY)
+            });
+            eprintln!("copied {:?} blocks", clipboard.dims().as_slice());
+            self.clipboard = Some(clipboard);
+            return;
+        }
+
+        if tool == Tool::LoadSchematic {
+            match schematic::load(SCHEMATIC_FILE) {
+                Ok(clipboard) => {
+                    eprintln!("loaded {:?} blocks from {}", clipboard.dims().as_slice(), SCHEMATIC_FILE);
+                    self.clipboard = Some(clipboard);
                 }
+                Err(err) => eprintln!("failed to load {}: {}", SCHEMATIC_FILE, err),
             }
+            return;
+        }
 
-            if offset.z == 0 {
-                if let Some(chunk) = self.stage.at_absolute_mut(coords - V3::z()) {
-                    chunk.mesh = None;
+        let clipboard = match &self.clipboard {
+            Some(clipboard) => clipboard,
+            None => {
+                eprintln!("nothing copied yet");
+                return;
+            }
+        };
+
+        match tool {
+            Tool::Rotate => { self.clipboard = Some(world_edit::rotate(clipboard, 1)); }
+            Tool::Mirror => { self.clipboard = Some(world_edit::mirror(clipboard)); }
+
+            Tool::SaveSchematic => {
+                match schematic::save(SCHEMATIC_FILE, clipboard) {
+                    Ok(()) => eprintln!("saved to {}", SCHEMATIC_FILE),
+                    Err(err) => eprintln!("failed to save {}: {}", SCHEMATIC_FILE, err),
                 }
             }
+
+            Tool::Paste => {
+                // the clipboard goes where a placed block would
+                let target = self.build_block;
+                let clipboard = self.clipboard.take().unwrap();
+                let changes = world_edit::paste(&clipboard, target, |block| {
+                    self.block_at(block).unwrap_or(Block::EMPTY)
+                });
+                self.clipboard = Some(clipboard);
+                self.apply_edit(tool, &changes);
+            }
+
+            _ => unreachable!(),
         }
+    }
 
-        self.edit_timer = EDIT_INTERVAL;
+    /// Makes a world-edit tool's changes as a single undoable transaction
+    fn apply_edit(&mut self, tool: Tool, changes: &[(BlockCoords, Block)]) {
+        self.journal.begin();
+        let mut written = 0;
+        for &(block, value) in changes {
+            if self.set_block(block, value) {
+                written += 1;
+            }
+        }
+        self.journal.commit();
+
+        eprintln!("{:?}: {} blocks changed", tool, written);
+        if written < changes.len() {
+            eprintln!("{:?}: {} blocks are in chunks not made yet", tool, changes.len() - written);
+        }
+    }
+
+    /// Writes a block, recording the change in the open journal transaction,
+    /// and returns whether it could be written
+    fn set_block(&mut self, block: BlockCoords, value: Block) -> bool {
+        match self.write_block(block, value) {
+            Some(old) => {
+                self.journal.record(Change { block, old, new: value });
+                true
+            }
+            None => false,
+        }
     }
 
-    fn world_clip(&self, hitbox: Option<Box3>, segment: Segment)
-        -> Option<(BlockCoords, box3::Intersection)>
+    fn undo_redo(&mut self, inputs: &Inputs) {
+        let undo = inputs.undo && !self.undo_held;
+        let redo = inputs.redo && !self.redo_held;
+        self.undo_held = inputs.undo;
+        self.redo_held = inputs.redo;
+
+        let changes = if undo {
+            self.journal.undo()
+        }
+        else if redo {
+            self.journal.redo()
+        }
+        else {
+            None
+        };
+
+        for change in changes.into_iter().flatten() {
+            self.write_block(change.block, change.new);
+        }
+    }
+
+    /// Boxes of the solid blocks which `hitbox` could touch moving by
+    /// `motion`, allowing for it rising by `step_height` on the way, taken
+    /// from each block's model
+    fn solids_near(&self, hitbox: &Box3, position: P3, motion: V3, step_height: f32)
+        -> Vec<Box3>
     {
-        let mins = ChunkCoords::containing(segment.source()) - V3::repeat(1);
-        let maxs = ChunkCoords::containing(segment.destination()) + V3::repeat(2);
-        let range = SpaceIter::new(mins.unwrap(), maxs.unwrap());
-
-        // TODO test a more sensible set of chunks/blocks
-        let mut nearest_hit: Option<(BlockCoords, box3::Intersection)> = None;
-        for coords in range.map(|coords| ChunkCoords::new(coords.into())) {
-            let chunk = if let Some(chunk) = self.stage.at_absolute(coords) {
-                &chunk.chunk
+        let start = hitbox.at(position);
+        let end = hitbox.at(position + motion);
+        let mins = start.mins().coords.zip_map(&end.mins().coords, f32::min);
+        let maxs = start.maxs().coords.zip_map(&end.maxs().coords, f32::max) + V3::z() * step_height;
+
+        let lo = mins.map(|x| x.floor() as i32 - 1);
+        let hi = maxs.map(|x| x.floor() as i32 + 2);
+
+        let mut solids = Vec::new();
+        let mut add = |coords: BlockCoords, block: Block| {
+            let def = self.registry.get(block);
+            if def.solid {
+                let mins = P3::from(coords.unwrap_f32());
+                solids.extend(
+                    def.model.boxes(def.orient, block.facing()).into_iter()
+                        .map(|model_box| model_box.in_world(mins))
+                );
             }
-            else {
-                // TODO improve behaviour when nearby chunks are not loaded
-                //      should probably be an error
-                return None;
+        };
+
+        // chunks filled with one block are taken whole, without indexing them
+        let first = BlockCoords::new(lo.into()).chunk();
+        let last = BlockCoords::new((hi - V3::repeat(1)).into()).chunk();
+        for chunk_coords in SpaceIter::new(first.unwrap(), last.unwrap() + V3::repeat(1)) {
+            let chunk_coords = ChunkCoords::new(chunk_coords.into());
+            let chunk = match self.stage.at_absolute(chunk_coords) {
+                Some(stage_chunk) => &stage_chunk.chunk,
+                None              => { continue; }
             };
 
-            if let Some((block, ixn)) = chunk_clip(coords, chunk, hitbox, segment) {
-                if ixn.lambda < nearest_hit.map(|(_, nh)| nh.lambda).unwrap_or(std::f32::INFINITY) {
-                    nearest_hit = Some((block, ixn));
-                }
+            let uniform = chunk.uniform();
+            if uniform.map_or(false, |block| !self.registry.get(block).solid) {
+                continue;
+            }
+
+            let origin = chunk_coords.block_mins().unwrap();
+            let from = lo.zip_map(&origin, i32::max);
+            let to = hi.zip_map(&(origin + V3::repeat(chunk::DIM)), i32::min);
+            for at in SpaceIter::new(from, to) {
+                let coords = BlockCoords::new(at.into());
+                add(coords, uniform.unwrap_or_else(|| chunk[coords.offset()]));
             }
         }
 
-        nearest_hit
+        solids
+    }
+
+    fn chunks_loaded_around(&self, point: P3) -> bool {
+        let center = ChunkCoords::containing(point);
+        SpaceIter::new(V3::repeat(-1), V3::repeat(2))
+            .all(|step| self.stage.at_absolute(center + step).is_some())
     }
 
     fn move_player(&mut self, inputs: &Inputs, dt: f32) {
-        let move_intent = {
+        if inputs.fly && !self.fly_held {
+            self.movement = match self.movement {
+                Movement::Walking => Movement::Flying,
+                Movement::Flying  => Movement::Walking,
+            };
+            self.player_velocity = V3::zeros();
+            self.on_ground = false;
+        }
+        self.fly_held = inputs.fly;
+
+        let walk_intent = {
             let move_fore = self.player_facing.flat();
             let move_right = V2::new(move_fore.y, -move_fore.x);
 
             (inputs.fore  as i32 - inputs.back as i32) as f32 * move_fore.push(0.) +
-            (inputs.right as i32 - inputs.left as i32) as f32 * move_right.push(0.) +
-            (inputs.up    as i32 - inputs.down as i32) as f32 * V3::z()
+            (inputs.right as i32 - inputs.left as i32) as f32 * move_right.push(0.)
         };
 
         let speed = PLAYER_SPEED * if inputs.fast { SPRINT_FACTOR } else { 1. };
 
-        let player_box = Box3::with_dims(P3::new(-0.4, -0.4, 0.0), V3::new(0.8, 0.8, 1.6));
+        match self.movement {
+            Movement::Flying => {
+                let climb = (inputs.up as i32 - inputs.down as i32) as f32;
+                self.player_velocity = speed * (walk_intent + climb * V3::z());
+            }
 
-        let mut remaining = 1.;
-        let mut stride = dt * speed * move_intent;
+            Movement::Walking => {
+                // without the terrain around us there is nothing to stand on
+                if !self.chunks_loaded_around(self.player_position) {
+                    self.player_velocity = V3::zeros();
+                    return;
+                }
 
-        while remaining > 0. {
-            let motion = Segment::new(self.player_position, stride);
-            let nearest_hit = self.world_clip(Some(player_box), motion);
+                let fall = self.player_velocity.z;
+                self.player_velocity = speed * walk_intent;
 
-            if let Some((_, hit)) = nearest_hit {
-                dbg!(hit.normal);
-                //dbg!(hit.lambda);
-                self.player_position += hit.lambda * stride;
-                remaining *= 1. - hit.lambda;
-                stride += hit.normal * -hit.normal.dot(&stride);
-            }
-            else {
-                self.player_position += stride;
-                break;
+                self.player_velocity.z = if self.on_ground && inputs.up {
+                    JUMP_SPEED
+                }
+                else {
+                    (fall - GRAVITY * dt).max(-TERMINAL_VELOCITY)
+                };
             }
         }
+
+        let player_box = Box3::with_dims(P3::new(-0.4, -0.4, 0.0), V3::new(0.8, 0.8, 1.6));
+        let motion = dt * self.player_velocity;
+
+        let resolver = match self.movement {
+            Movement::Walking => Resolver::new(STEP_HEIGHT),
+            Movement::Flying  => Resolver::new(0.),
+        };
+
+        let solids = self.solids_near(&player_box, self.player_position, motion, resolver.step_height);
+        let resolved = resolver.resolve(&player_box, self.player_position, motion, &solids);
+
+        self.player_position += resolved.motion;
+        self.on_ground = resolved.floor;
+
+        // landing or bumping a ceiling stops vertical motion
+        if resolved.floor || resolved.ceiling {
+            self.player_velocity.z = 0.;
+        }
     }
 
     pub fn tick(&mut self, inputs: &Inputs, dt: f32) {
@@ -480,45 +1115,173 @@ impl Game {
         self.move_player(inputs, dt);
         self.update_chunks();
         self.edit_blocks(inputs, dt);
+        if let Some(tool) = inputs.tool {
+            self.use_tool(tool);
+        }
+        self.undo_redo(inputs);
+
+        if let Some(command) = inputs.clock {
+            self.clock.apply(command);
+        }
+        self.clock.tick(dt);
 
         self.zoom = inputs.zoom;
+
+        self.sync_timer -= dt;
+        if self.sync_timer <= 0. {
+            self.save();
+            self.sync_timer = SYNC_INTERVAL;
+        }
     }
 
+    /// Sends stale chunks off to be meshed, and uploads finished meshes until
+    /// the frame's budget runs out
     fn refresh_meshes(&mut self) {
+        // meshing reads all 26 neighbours, so chunks on the stage's faces are
+        // never meshed
         for rel in SpaceIter::new(
-            self.stage.relative_mins(),
+            self.stage.relative_mins() + V3::repeat(1),
             self.stage.relative_maxs() - V3::repeat(1))
         {
             match self.stage.at_relative(rel) {
-                Some(chunk) if chunk.mesh.is_none() => { }
+                Some(chunk) if chunk.mesh_state == MeshState::Stale => { }
                 _ => { continue; }
             };
 
-            let mesh = {
-                //eprintln!("meshing chunk {}", rel);
-                //let abs = self.stage.relative_to_absolute(rel);
-                let ok = fill_meshing_buffer(&mut self.mesh_buf, &self.stage, rel);
-                if ok.is_none() { continue; }
-                (self.mesher)(self.mesh_buf.whole_slice())
+            if is_featureless(&self.stage, &self.registry, rel) {
+                let chunk = self.stage.at_relative_mut(rel).unwrap();
+                chunk.mesh = Some(Rc::new(mesher::EmptyMesh));
+                chunk.mesh_state = MeshState::Current;
+                continue;
+            }
+
+            let mut buffer = Box::new(MeshingBuffer::new_filled(Block::EMPTY));
+            let mut light = Box::new(MeshingLight::new_filled(0));
+            let ok = fill_meshing_buffer(&mut buffer, &mut light, &self.stage, rel);
+            if ok.is_none() { continue; }
+
+            let id = self.next_mesh_id;
+            self.next_mesh_id += 1;
+
+            let coords = self.stage.relative_to_absolute(rel);
+            self.stage.at_relative_mut(rel).unwrap().mesh_state = MeshState::Pending(id);
+            self.mesh_workers.submit(MeshJob { coords, id, buffer, light });
+        }
+
+        self.meshes_ready.extend(self.mesh_workers.try_iter());
+
+        let start = Instant::now();
+        while start.elapsed() < MESH_UPLOAD_BUDGET {
+            let MeshResult { coords, id, quads } = match self.meshes_ready.pop_front() {
+                Some(result) => result,
+                None         => { break; }
             };
 
-            if let Some(chunk) = self.stage.at_relative_mut(rel) {
-                chunk.mesh = Some(mesh);
+            // the chunk may have been edited or left the stage since
+            let chunk = match self.stage.at_absolute_mut(coords) {
+                Some(chunk) if chunk.mesh_state == MeshState::Pending(id) => chunk,
+                _ => { continue; }
+            };
+
+            let mesh: Rc<dyn mesher::Mesh> = if quads.is_empty() {
+                Rc::new(mesher::EmptyMesh)
             }
+            else {
+                Rc::new(mesher::InstancedQuadMesh::upload(&quads))
+            };
+
+            chunk.mesh = Some(mesh);
+            chunk.mesh_state = MeshState::Current;
         }
     }
 
+    pub fn held_block(&self) -> &BlockDef {
+        self.registry.get(self.held_block)
+    }
+
+    /// The block the player is looking at, and how far away it is
+    pub fn target(&self) -> Option<(&BlockDef, f32)> {
+        let distance = self.hit_distance?;
+        let (coords, offset) = self.selected_block.chunk_and_offset();
+        let block = self.stage.at_absolute(coords)?.chunk[offset];
+        Some((self.registry.get(block), distance))
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn draw_stats(&self) -> DrawStats {
+        self.draw_stats
+    }
+
+    pub fn cache_stats(&self) -> chunk_cache::Stats {
+        self.source.cache_stats()
+    }
+
+    /// How many chunks are being made by the workers
+    pub fn chunks_loading(&self) -> usize {
+        self.source.in_flight()
+    }
+
     fn eye_position(&self) -> P3 {
         self.player_position + 1.5f32 * V3::z()
     }
 
+    /// Draws one pass of a staged chunk's mesh, with the quad shader bound
+    fn draw_chunk(
+        &self,
+        chunk_coords: ChunkCoords,
+        pass: mesher::Pass,
+        world_to_view: &M4,
+        view_to_clip: &Perspective)
+    {
+        let mesh = match self.stage.at_absolute(chunk_coords).and_then(|chunk| chunk.mesh.as_ref()) {
+            Some(mesh) => mesh,
+            None       => { return; }
+        };
+
+        let model_to_world = na::Translation::from(
+            chunk_coords.unwrap().map(|x| (x * chunk::DIM) as f32)
+        );
+
+        let model_to_view
+            = world_to_view
+            * model_to_world.to_homogeneous();
+
+        let model_to_clip
+            = view_to_clip.as_matrix()
+            * model_to_view;
+
+        unsafe {
+            gl::UniformMatrix4fv(0, 1, gl::FALSE, model_to_clip.as_ptr());
+            gl::UniformMatrix4fv(7, 1, gl::FALSE, model_to_view.as_ptr());
+        }
+
+        let selected_offset = self.selected_block - chunk_coords.block_mins();
+        // TODO try_map = transpose . map
+        let selected = if
+            (-1 ..= chunk::DIM).contains(&selected_offset.x) &&
+            (-1 ..= chunk::DIM).contains(&selected_offset.y) &&
+            (-1 ..= chunk::DIM).contains(&selected_offset.z)
+        {
+            Some(selected_offset.map(|x| x as i8))
+        }
+        else {
+            None
+        };
+
+        mesh.draw(pass, selected);
+    }
+
     pub fn draw(&mut self, screen_dims: V2) {
         self.refresh_meshes();
 
         let aspect = screen_dims.x / screen_dims.y;
 
         let fov = FOV * if self.zoom { 1. / ZOOM_FACTOR } else { 1. };
-        let view_to_clip = Perspective::new(aspect, fov * (PI / 180.0), 0.1, 1000.0);
+        // nothing past the fog's end can be seen anyway
+        let view_to_clip = Perspective::new(aspect, fov * (PI / 180.0), 0.1, FOG_END + chunk::DIM as f32);
 
         let eye_position = self.eye_position();
 
@@ -534,47 +1297,156 @@ impl Game {
 
         unsafe {
             gl::Viewport(0, 0, screen_dims.x as i32, screen_dims.y as i32);
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
         }
 
+        // the sky is drawn as though around the eye, however far it moves
+        let eye_turn = Motion::look_at_rh(
+            &P3::origin(),
+            &P3::from(self.player_facing.direction()),
+            &V3::z()
+        ).to_homogeneous();
+        let horizon = self.clock.horizon_color();
+        self.sky.draw(&(view_to_clip.as_matrix() * eye_turn), horizon, self.clock.zenith_color());
+
+        unsafe { self.shader.bind(); }
         self.atlas.bind();
+        let light_direction = self.clock.light_direction();
+        unsafe {
+            gl::Uniform1f(5, self.clock.daylight());
+            gl::Uniform3fv(6, 1, light_direction.as_ptr());
+            gl::Uniform2f(8, FOG_START, FOG_END);
+            gl::Uniform3fv(9, 1, horizon.as_ptr());
+        }
+
+        let frustum = Frustum::from_clip_matrix(&world_to_clip);
+        let mut stats = DrawStats::default();
 
+        let mut visible = Vec::new();
         for chunk_coords in self.stage.absolute_coords_iter() {
-            if let Some(chunk) = self.stage.at_absolute(chunk_coords) {
-                if let Some(mesh) = &chunk.mesh {
-                    let model_to_world = na::Translation::from(
-                        chunk_coords.unwrap().map(|x| (x * chunk::DIM) as f32)
-                    );
-
-                    let model_to_clip
-                        = world_to_clip
-                        * model_to_world.to_homogeneous();
-
-                    unsafe {
-                        gl::UniformMatrix4fv(0, 1, gl::FALSE, model_to_clip.as_ptr());
-
-                        gl::Uniform2fv(2, 1, self.atlas.tile_dims().as_ptr() as *const _);
-                        gl::Uniform2fv(3, 1, self.atlas.padding().as_ptr() as *const _);
-                        gl::Uniform2fv(4, 1, self.atlas.stride().as_ptr() as *const _);
-                    }
-
-                    let selected_offset = self.selected_block - chunk_coords.block_mins();
-                    // TODO try_map = transpose . map
-                    let selected = if
-                        (-1 ..= chunk::DIM).contains(&selected_offset.x) &&
-                        (-1 ..= chunk::DIM).contains(&selected_offset.y) &&
-                        (-1 ..= chunk::DIM).contains(&selected_offset.z)
-                    {
-                        Some(selected_offset.map(|x| x as i8))
-                    }
-                    else {
-                        None
-                    };
-
-                    mesh.draw(selected);
-                }
+            let has_mesh = self.stage.at_absolute(chunk_coords)
+                .map_or(false, |chunk| chunk.mesh.is_some());
+            if !has_mesh {
+                continue;
+            }
+
+            let bounds = Box3::with_dims(
+                chunk_coords.block_mins().unwrap_f32().into(),
+                V3::repeat(chunk::DIM as f32)
+            );
+
+            if frustum.may_see(&bounds) {
+                visible.push(chunk_coords);
+            }
+            else {
+                stats.culled += 1;
             }
         }
+        stats.drawn = visible.len();
+
+        unsafe {
+            gl::Uniform2fv(2, 1, self.atlas.tile_dims().as_ptr() as *const _);
+            gl::Uniform2fv(3, 1, self.atlas.padding().as_ptr() as *const _);
+            gl::Uniform2fv(4, 1, self.atlas.stride().as_ptr() as *const _);
+
+            gl::Uniform1f(10, OPAQUE_ALPHA_CUTOFF);
+            gl::Disable(gl::BLEND);
+        }
+        for &chunk_coords in &visible {
+            self.draw_chunk(chunk_coords, mesher::Pass::Opaque, &world_to_view, &view_to_clip);
+        }
+
+        // blended faces only look right over what's behind them, so they're
+        // drawn after everything opaque, and the farthest chunks first
+        let chunk_center = |coords: ChunkCoords| {
+            coords.block_mins().unwrap_f32() + V3::repeat(chunk::DIM as f32 / 2.)
+        };
+        visible.sort_by_key(|&coords| {
+            std::cmp::Reverse(OrdFloat((chunk_center(coords) - eye_position.coords).norm_squared()))
+        });
+
+        unsafe {
+            gl::Uniform1f(10, 0.);
+            gl::Enable(gl::BLEND);
+            gl::DepthMask(gl::FALSE);
+        }
+        for &chunk_coords in &visible {
+            self.draw_chunk(chunk_coords, mesher::Pass::Translucent, &world_to_view, &view_to_clip);
+        }
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+        }
+
+        self.draw_stats = stats;
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::block::stock::{self, *},
+    };
+
+    /// A floor of stone at z = 0 with a slab on it at x = 3, within 8
+    /// blocks of the origin
+    fn world(block: BlockCoords) -> Option<Block> {
+        let at = block.unwrap();
+        if at.iter().any(|x| x.abs() > 8) {
+            None
+        }
+        else if at.z == 0 {
+            Some(STONE)
+        }
+        else if at == V3::new(3, 0, 1) {
+            Some(STONE_SLAB)
+        }
+        else {
+            Some(Block::EMPTY)
+        }
+    }
+
+    #[test]
+    fn raycast_axis_aligned() {
+        let registry = stock::registry();
+
+        let down = Segment::new(P3::new(0.5, 0.5, 3.5), V3::new(0., 0., -4.));
+        let hit = raycast(down, &registry, world).unwrap();
+        assert_eq!(hit.block, BlockCoords::new(P3::new(0, 0, 0)));
+        assert_eq!(hit.normal, V3::new(0, 0, 1));
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+
+        // the slab is struck on its top, half way down its block
+        let onto_slab = Segment::new(P3::new(3.5, 0.5, 3.5), V3::new(0., 0., -4.));
+        let hit = raycast(onto_slab, &registry, world).unwrap();
+        assert_eq!(hit.block, BlockCoords::new(P3::new(3, 0, 1)));
+        assert!((hit.distance - 2.).abs() < 1e-5);
+
+        // over the slab, through the upper half of its block
+        let level = Segment::new(P3::new(0.5, 0.5, 1.75), V3::new(4., 0., 0.));
+        assert_eq!(raycast(level, &registry, world), None);
+
+        let into_slab = Segment::new(P3::new(0.5, 0.5, 1.25), V3::new(4., 0., 0.));
+        let hit = raycast(into_slab, &registry, world).unwrap();
+        assert_eq!(hit.normal, V3::new(-1, 0, 0));
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn raycast_diagonal() {
+        let registry = stock::registry();
+        // 1.5 long, so falling the 1.5 to the floor covers 2.25
+        let direction = V3::new(1., 0.5, -1.);
+
+        let segment = Segment::new(P3::new(0.3, 0.6, 2.5), direction * 2.);
+        let hit = raycast(segment, &registry, world).unwrap();
+        assert_eq!(hit.block, BlockCoords::new(P3::new(1, 1, 0)));
+        assert_eq!(hit.normal, V3::new(0, 0, 1));
+        assert!((hit.distance - 2.25).abs() < 1e-5);
+
+        // stops where the world can't be read
+        let far = Segment::new(P3::new(7.5, 0.5, 2.5), V3::new(8., 0., 0.));
+        assert_eq!(raycast(far, &registry, world), None);
+    }
+}
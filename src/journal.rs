@@ -0,0 +1,171 @@
+
+use {
+    crate::{
+        block::Block,
+        chunk::BlockCoords,
+    },
+    std::collections::VecDeque,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Change {
+    pub block: BlockCoords,
+    pub old:   Block,
+    pub new:   Block,
+}
+
+impl Change {
+    pub fn inverse(self) -> Change {
+        Change { block: self.block, old: self.new, new: self.old }
+    }
+}
+
+/// Changes which are undone and redone together, in the order they were made
+pub type Transaction = Vec<Change>;
+
+/// A history of block edits, grouped into transactions
+///
+/// Changes are recorded between `begin` and `commit`. Undoing hands back the
+/// changes needed to reverse the latest transaction, and redoing those needed
+/// to reapply the latest one undone. Committing anything new forgets what
+/// could have been redone. Only the most recent `limit` transactions are
+/// kept.
+pub struct Journal {
+    undo:  VecDeque<Transaction>,
+    redo:  Vec<Transaction>,
+    open:  Option<Transaction>,
+    limit: usize,
+}
+
+impl Journal {
+    pub fn new(limit: usize) -> Journal {
+        Journal {
+            undo:  VecDeque::new(),
+            redo:  Vec::new(),
+            open:  None,
+            limit,
+        }
+    }
+
+    pub fn begin(&mut self) {
+        assert!(self.open.is_none(), "transaction already open");
+        self.open = Some(Vec::new());
+    }
+
+    /// Adds a change to the open transaction, if there is one
+    pub fn record(&mut self, change: Change) {
+        if change.old == change.new {
+            return;
+        }
+
+        if let Some(open) = &mut self.open {
+            open.push(change);
+        }
+    }
+
+    /// Closes the open transaction, keeping it if anything changed
+    pub fn commit(&mut self) {
+        let transaction = self.open.take().expect("no transaction open");
+        if transaction.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        self.undo.push_back(transaction);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Takes the changes which reverse the latest transaction, to be applied
+    /// in order
+    pub fn undo(&mut self) -> Option<Transaction> {
+        let transaction = self.undo.pop_back()?;
+        let reversed = transaction.iter()
+            .rev()
+            .map(|change| change.inverse())
+            .collect();
+        self.redo.push(transaction);
+        Some(reversed)
+    }
+
+    /// Takes the changes which reapply the latest transaction undone
+    pub fn redo(&mut self) -> Option<Transaction> {
+        let transaction = self.redo.pop()?;
+        self.undo.push_back(transaction.clone());
+        Some(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            block::stock::*,
+            math::*,
+        },
+    };
+
+    fn change(x: i32, old: Block, new: Block) -> Change {
+        Change { block: BlockCoords::new(P3::new(x, 0, 0)), old, new }
+    }
+
+    fn commit(journal: &mut Journal, changes: &[Change]) {
+        journal.begin();
+        for change in changes {
+            journal.record(*change);
+        }
+        journal.commit();
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut journal = Journal::new(10);
+        let a = change(0, Block::EMPTY, STONE);
+        let b = change(1, GRASS, Block::EMPTY);
+        let c = change(0, STONE, SOIL);
+
+        commit(&mut journal, &[a, b]);
+        commit(&mut journal, &[c]);
+
+        assert_eq!(journal.undo(), Some(vec![c.inverse()]));
+        assert_eq!(journal.undo(), Some(vec![b.inverse(), a.inverse()]));
+        assert_eq!(journal.undo(), None);
+
+        assert_eq!(journal.redo(), Some(vec![a, b]));
+        assert_eq!(journal.undo(), Some(vec![b.inverse(), a.inverse()]));
+        assert_eq!(journal.redo(), Some(vec![a, b]));
+        assert_eq!(journal.redo(), Some(vec![c]));
+        assert_eq!(journal.redo(), None);
+    }
+
+    #[test]
+    fn new_edits_forget_redo() {
+        let mut journal = Journal::new(10);
+        commit(&mut journal, &[change(0, Block::EMPTY, STONE)]);
+        journal.undo().unwrap();
+
+        // unchanged blocks don't make a transaction
+        commit(&mut journal, &[change(2, SOIL, SOIL)]);
+        assert!(journal.redo().is_some());
+        journal.undo().unwrap();
+
+        commit(&mut journal, &[change(1, Block::EMPTY, GRASS)]);
+        assert_eq!(journal.redo(), None);
+        assert_eq!(journal.undo(), Some(vec![change(1, GRASS, Block::EMPTY)]));
+        assert_eq!(journal.undo(), None);
+    }
+
+    #[test]
+    fn limited() {
+        let mut journal = Journal::new(2);
+        for x in 0 .. 5 {
+            commit(&mut journal, &[change(x, Block::EMPTY, STONE)]);
+        }
+
+        assert_eq!(journal.undo(), Some(vec![change(4, STONE, Block::EMPTY)]));
+        assert_eq!(journal.undo(), Some(vec![change(3, STONE, Block::EMPTY)]));
+        assert_eq!(journal.undo(), None);
+    }
+}
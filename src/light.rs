@@ -0,0 +1,786 @@
+use {
+    crate::{
+        array3d,
+        block::{Block, Registry},
+        chunk::{self, BlockCoords, Chunk},
+        math::*,
+        mesher::Direction,
+    },
+    std::collections::{HashMap, VecDeque},
+};
+
+/// The brightest light there is, given off by the brightest light sources and
+/// by the open sky
+pub const MAX_LEVEL: u8 = 15;
+
+pub type Array     = array3d::ArrayOwned<u8, chunk::Dims>;
+pub type Slice<'a> = array3d::ArraySlice<'a, u8>;
+
+/// The two kinds of light, each spreading on its own
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    /// Given off by blocks
+    Block,
+    /// Falling from the open sky, as bright by night as by day; it is dimmed
+    /// by the daylight as it is drawn
+    Sky,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 2] = [Channel::Block, Channel::Sky];
+
+    fn shift(self) -> u8 {
+        match self {
+            Channel::Block => 0,
+            Channel::Sky   => 4,
+        }
+    }
+
+    /// The channel's level out of a block's packed light
+    pub fn of(self, packed: u8) -> u8 {
+        (packed >> self.shift()) & MAX_LEVEL
+    }
+
+    fn with(self, packed: u8, level: u8) -> u8 {
+        (packed & !(MAX_LEVEL << self.shift())) | (level << self.shift())
+    }
+}
+
+/// The light of each block in a chunk, with both channels packed into a byte
+///
+/// Like a chunk's blocks, light takes no per-voxel storage while it is the
+/// same throughout, as it is in open air and deep underground.
+#[derive(Clone)]
+pub struct Levels {
+    storage: Storage,
+}
+
+#[derive(Clone)]
+enum Storage {
+    Dense(Array),
+    Uniform(u8),
+}
+
+impl Levels {
+    pub fn new_dark() -> Levels {
+        Levels { storage: Storage::Uniform(0) }
+    }
+
+    pub fn get(&self, offset: V3u8) -> u8 {
+        match &self.storage {
+            Storage::Dense(array)    => array[offset.map(|x| x as usize)],
+            Storage::Uniform(packed) => *packed,
+        }
+    }
+
+    pub fn set(&mut self, offset: V3u8, packed: u8) {
+        if let Storage::Uniform(uniform) = self.storage {
+            if uniform == packed {
+                return;
+            }
+            self.storage = Storage::Dense(Array::new_filled(uniform));
+        }
+
+        if let Storage::Dense(array) = &mut self.storage {
+            array[offset.map(|x| x as usize)] = packed;
+        }
+    }
+
+    /// Drops the per-voxel storage if every block is lit the same
+    pub fn compact(&mut self) {
+        if let Storage::Dense(array) = &self.storage {
+            let first = array[V3::zeros()];
+            if array.iter().all(|packed| *packed == first) {
+                self.storage = Storage::Uniform(first);
+            }
+        }
+    }
+
+    /// Copies the light starting at `offset` into `dst`, which determines the
+    /// extent of the copied region
+    pub fn copy_into(&self, offset: V3usize, dst: &mut array3d::ArraySliceMut<'_, u8>) {
+        match &self.storage {
+            Storage::Dense(array)    => dst.copy_from(&array.slice(offset, dst.dims())),
+            Storage::Uniform(packed) => dst.iter_mut().for_each(|l| *l = *packed),
+        }
+    }
+}
+
+const COLUMN_DIM:  usize = chunk::DIM as usize;
+const COLUMN_AREA: usize = COLUMN_DIM * COLUMN_DIM;
+
+/// The column of blocks at an index into a chunk's columns, as used by
+/// `Heights`
+fn column_at(mins: V3i32, index: usize) -> V2i32 {
+    mins.xy() + V2::new((index % COLUMN_DIM) as i32, (index / COLUMN_DIM) as i32)
+}
+
+/// The height of the highest block in each column which sky light can't pass
+///
+/// Heights are kept for whole columns of chunks, covering every chunk of the
+/// column that has been loaded, and are `i32::MIN` where nothing stops the
+/// light.
+pub struct Heights {
+    columns: HashMap<V2i32, Box<[i32; COLUMN_AREA]>>,
+}
+
+impl Heights {
+    pub fn new() -> Heights {
+        Heights { columns: HashMap::new() }
+    }
+
+    fn split(column: V2i32) -> (V2i32, usize) {
+        let offset = column.map(|x| (x & chunk::DIM_MASK) as usize);
+        (column.map(|x| x >> chunk::DIM_LOG2), offset.y * COLUMN_DIM + offset.x)
+    }
+
+    pub fn get(&self, column: V2i32) -> i32 {
+        let (chunk_column, index) = Heights::split(column);
+        self.columns.get(&chunk_column).map_or(i32::MIN, |heights| heights[index])
+    }
+
+    pub fn set(&mut self, column: V2i32, height: i32) {
+        let (chunk_column, index) = Heights::split(column);
+        let heights = self.columns.entry(chunk_column)
+            .or_insert_with(|| Box::new([i32::MIN; COLUMN_AREA]));
+        heights[index] = height;
+    }
+
+    /// Forgets every column of chunks, by chunk coordinates, for which `keep`
+    /// is false
+    pub fn retain(&mut self, mut keep: impl FnMut(V2i32) -> bool) {
+        self.columns.retain(|chunk_column, _| keep(*chunk_column));
+    }
+}
+
+/// Blocks and their light, spanning however many chunks are loaded
+pub trait World {
+    /// The block at a position, or `None` where nothing is loaded
+    fn block(&self, at: BlockCoords) -> Option<Block>;
+    /// The packed light at a position, 0 where nothing is loaded
+    fn packed_light(&self, at: BlockCoords) -> u8;
+    /// Sets the packed light at a position, if it is loaded
+    fn set_packed_light(&mut self, at: BlockCoords, packed: u8);
+
+    fn heights(&self) -> &Heights;
+    fn heights_mut(&mut self) -> &mut Heights;
+    /// The lowest Z at which anything can be loaded
+    fn bottom(&self) -> i32;
+
+    fn light(&self, at: BlockCoords, channel: Channel) -> u8 {
+        channel.of(self.packed_light(at))
+    }
+
+    fn set_light(&mut self, at: BlockCoords, channel: Channel, level: u8) {
+        let packed = self.packed_light(at);
+        self.set_packed_light(at, channel.with(packed, level));
+    }
+}
+
+/// Whether light can spread into a block
+fn lets_through(registry: &Registry, block: Block) -> bool {
+    block.is_empty() || !registry.get(block).is_opaque_cube()
+}
+
+/// The light a block has of its own, whatever reaches it from elsewhere
+fn own_level(world: &impl World, registry: &Registry, at: BlockCoords, channel: Channel) -> u8 {
+    let block = match world.block(at) {
+        Some(block) => block,
+        None        => { return 0; }
+    };
+
+    match channel {
+        Channel::Block => registry.get(block).light,
+        Channel::Sky   => {
+            let open = at.unwrap().z > world.heights().get(at.unwrap().xy());
+            if open { MAX_LEVEL } else { 0 }
+        }
+    }
+}
+
+/// What lighting a chunk needs to know about its blocks, gathered before it
+/// joins the world
+pub struct Survey {
+    /// The light sources among the chunk's blocks, by offset, with their levels
+    sources: Vec<(V3u8, u8)>,
+    /// The offset along Z of the highest block stopping light in each column
+    tops:    Vec<Option<u8>>,
+}
+
+impl Survey {
+    pub fn new(registry: &Registry, chunk: &Chunk) -> Survey {
+        const DIM: u8 = chunk::DIM as u8;
+
+        if let Some(block) = chunk.uniform() {
+            let top = if lets_through(registry, block) { None } else { Some(DIM - 1) };
+            let sources = match registry.get(block).light {
+                0     => Vec::new(),
+                level => chunk.indices().map(|ijk| (ijk.map(|x| x as u8), level)).collect(),
+            };
+            return Survey { sources, tops: vec![top; COLUMN_AREA] };
+        }
+
+        let sources = chunk.indexed_iter()
+            .map(|(ijk, block)| (ijk.map(|x| x as u8), registry.get(block).light))
+            .filter(|(_, level)| *level > 0)
+            .collect();
+
+        let tops = (0 .. COLUMN_AREA)
+            .map(|i| {
+                let (x, y) = ((i % COLUMN_DIM) as u8, (i / COLUMN_DIM) as u8);
+                (0 .. DIM).rev().find(|z| !lets_through(registry, chunk[V3::new(x, y, *z)]))
+            })
+            .collect();
+
+        Survey { sources, tops }
+    }
+}
+
+/// Spreads light outward from the blocks in `queue`, brightening every block
+/// it reaches which is darker than the light arriving there
+fn spread(
+    world:     &mut impl World,
+    registry:  &Registry,
+    channel:   Channel,
+    mut queue: VecDeque<BlockCoords>,
+) {
+    while let Some(at) = queue.pop_front() {
+        let level = world.light(at, channel);
+        if level <= 1 {
+            continue;
+        }
+
+        for dir in &Direction::ALL {
+            let next = at + dir.normal();
+            match world.block(next) {
+                Some(block) if lets_through(registry, block) => { }
+                _ => { continue; }
+            }
+
+            if world.light(next, channel) < level - 1 {
+                world.set_light(next, channel, level - 1);
+                queue.push_back(next);
+            }
+        }
+    }
+}
+
+/// Takes away the light of the blocks in `seeds`, given with the levels they
+/// had, and all the light that spread from them
+///
+/// Returns the blocks whose light should spread back into the darkened area:
+/// those lit from elsewhere around its edge, and light sources within it.
+fn darken(
+    world:    &mut impl World,
+    registry: &Registry,
+    channel:  Channel,
+    seeds:    Vec<(BlockCoords, u8)>,
+)
+    -> VecDeque<BlockCoords>
+{
+    let mut relight = VecDeque::new();
+    let mut queue = VecDeque::new();
+    for (at, level) in seeds {
+        world.set_light(at, channel, 0);
+        queue.push_back((at, level));
+    }
+
+    while let Some((from, level)) = queue.pop_front() {
+        for dir in &Direction::ALL {
+            let next = from + dir.normal();
+            let next_level = world.light(next, channel);
+            if next_level == 0 {
+                continue;
+            }
+
+            if next_level < level {
+                world.set_light(next, channel, 0);
+                queue.push_back((next, next_level));
+
+                let own = own_level(world, registry, next, channel);
+                if own > 0 {
+                    world.set_light(next, channel, own);
+                    relight.push_back(next);
+                }
+            }
+            else {
+                relight.push_back(next);
+            }
+        }
+    }
+
+    relight
+}
+
+/// The loaded blocks of a column from `top` down to just above `floor`
+fn loaded_below(world: &impl World, column: V2i32, top: i32, floor: i32) -> Vec<BlockCoords> {
+    let floor = floor.max(world.bottom() - 1);
+    let mut blocks = Vec::new();
+    let mut z = top;
+    while z > floor {
+        let at = BlockCoords::new(column.push(z).into());
+        if world.block(at).is_some() {
+            blocks.push(at);
+            z -= 1;
+        }
+        else {
+            // on to the top of the chunk below
+            z = (z & !chunk::DIM_MASK) - 1;
+        }
+    }
+    blocks
+}
+
+/// Lights a chunk just added to the world, from its own light sources, the
+/// sky above it and the light already in its neighbours
+///
+/// Its light spreads on into its neighbours, and its blocks shade any below
+/// which were open to the sky.
+pub fn light_chunk(world: &mut impl World, registry: &Registry, coords: chunk::Coords, survey: &Survey) {
+    let mins = coords.block_mins().unwrap();
+    let mut block_queue = VecDeque::new();
+    let mut sky_queue = VecDeque::new();
+
+    let mut shaded = Vec::new();
+    for (i, top) in survey.tops.iter().enumerate() {
+        let column = column_at(mins, i);
+        let old = world.heights().get(column);
+        let top = match top {
+            Some(top) if mins.z + *top as i32 > old => mins.z + *top as i32,
+            _ => { continue; }
+        };
+
+        world.heights_mut().set(column, top);
+        for at in loaded_below(world, column, mins.z - 1, old) {
+            let level = world.light(at, Channel::Sky);
+            if level > 0 {
+                shaded.push((at, level));
+            }
+        }
+    }
+    sky_queue.extend(darken(world, registry, Channel::Sky, shaded));
+
+    for &(offset, level) in &survey.sources {
+        let at = coords.block_at_offset(offset);
+        if world.light(at, Channel::Block) < level {
+            world.set_light(at, Channel::Block, level);
+            block_queue.push_back(at);
+        }
+    }
+
+    // blocks open to the sky are fully lit, and need only spread sideways
+    // under neighbouring columns which are higher
+    for i in 0 .. COLUMN_AREA {
+        let column = column_at(mins, i);
+        let heights = world.heights();
+        let lowest = (heights.get(column) + 1).max(mins.z);
+        let sides = [
+            heights.get(column + V2::x()), heights.get(column - V2::x()),
+            heights.get(column + V2::y()), heights.get(column - V2::y()),
+        ];
+
+        for z in lowest .. mins.z + chunk::DIM {
+            let at = BlockCoords::new(column.push(z).into());
+            world.set_light(at, Channel::Sky, MAX_LEVEL);
+            if sides.iter().any(|side| *side >= z) {
+                sky_queue.push_back(at);
+            }
+        }
+    }
+
+    // the layer of each neighbour touching the chunk
+    for dir in &Direction::ALL {
+        let normal = dir.normal();
+        let (lo, hi) = (
+            mins + normal * chunk::DIM,
+            mins + normal * chunk::DIM + V3::repeat(chunk::DIM),
+        );
+        let layer = lo.zip_zip_map(&hi, &normal, |lo, hi, n| match n {
+            1  => (lo, lo + 1),
+            -1 => (hi - 1, hi),
+            _  => (lo, hi),
+        });
+
+        let (lo, hi) = (layer.map(|(lo, _)| lo), layer.map(|(_, hi)| hi));
+        for at in SpaceIter::new(lo, hi) {
+            let at = BlockCoords::new(at.into());
+            if world.light(at, Channel::Block) > 1 { block_queue.push_back(at); }
+            if world.light(at, Channel::Sky)   > 1 { sky_queue.push_back(at); }
+        }
+    }
+
+    spread(world, registry, Channel::Block, block_queue);
+    spread(world, registry, Channel::Sky, sky_queue);
+}
+
+/// Relights the world around a block which has just changed
+///
+/// The light which passed through or came from the block is taken away
+/// first, clearing every block it lit until meeting light from elsewhere,
+/// which then spreads back in along with any the new block gives off. The
+/// height of the block's column is kept up to date, opening up or shading
+/// the blocks below.
+pub fn block_changed(world: &mut impl World, registry: &Registry, at: BlockCoords) {
+    let block = match world.block(at) {
+        Some(block) => block,
+        None        => { return; }
+    };
+
+    let column = at.unwrap().xy();
+    let z = at.unwrap().z;
+    let height = world.heights().get(column);
+
+    let mut shaded = Vec::new();
+    let mut opened = Vec::new();
+    if !lets_through(registry, block) && z > height {
+        world.heights_mut().set(column, z);
+        for below in loaded_below(world, column, z - 1, height) {
+            shaded.push((below, world.light(below, Channel::Sky)));
+        }
+    }
+    else if lets_through(registry, block) && z == height {
+        let below = loaded_below(world, column, z - 1, i32::MIN);
+        let new_height = below.iter()
+            .find(|below| !lets_through(registry, world.block(**below).unwrap()))
+            .map_or(i32::MIN, |below| below.unwrap().z);
+
+        world.heights_mut().set(column, new_height);
+        opened.extend(below.into_iter().take_while(|below| below.unwrap().z > new_height));
+    }
+
+    for &channel in &Channel::ALL {
+        let mut seeds = vec![(at, world.light(at, channel))];
+        if channel == Channel::Sky {
+            seeds.append(&mut shaded);
+        }
+
+        let mut relight = darken(world, registry, channel, seeds);
+
+        let own = own_level(world, registry, at, channel);
+        if own > 0 {
+            world.set_light(at, channel, own);
+            relight.push_back(at);
+        }
+
+        if channel == Channel::Sky {
+            for &below in &opened {
+                world.set_light(below, channel, MAX_LEVEL);
+                relight.push_back(below);
+            }
+        }
+
+        spread(world, registry, channel, relight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::block::stock::*,
+        std::collections::HashSet,
+    };
+
+    /// A box of blocks with nothing loaded beyond its edges, and perhaps some
+    /// of its chunks not loaded yet
+    struct Room {
+        blocks:   HashMap<BlockCoords, Block>,
+        light:    HashMap<BlockCoords, u8>,
+        heights:  Heights,
+        size:     i32,
+        unloaded: HashSet<chunk::Coords>,
+    }
+
+    impl Room {
+        fn new(size: i32) -> Room {
+            Room {
+                blocks:   HashMap::new(),
+                light:    HashMap::new(),
+                heights:  Heights::new(),
+                size,
+                unloaded: HashSet::new(),
+            }
+        }
+
+        /// An empty room with all its chunks loaded, open to the sky
+        fn open(registry: &Registry, size: i32) -> Room {
+            let mut room = Room::new(size);
+            for coords in room.chunks() {
+                room.load(registry, coords);
+            }
+            room
+        }
+
+        fn contains(&self, at: BlockCoords) -> bool {
+            at.unwrap().iter().all(|x| (0 .. self.size).contains(x))
+        }
+
+        fn chunks(&self) -> Vec<chunk::Coords> {
+            let n = self.size / chunk::DIM;
+            SpaceIter::new(V3::zeros(), V3::repeat(n))
+                .map(|xyz| chunk::Coords::new(xyz.into()))
+                .collect()
+        }
+
+        /// Changes a block and relights around it
+        fn place(&mut self, registry: &Registry, at: BlockCoords, block: Block) {
+            self.blocks.insert(at, block);
+            block_changed(self, registry, at);
+        }
+
+        /// Loads a chunk of blocks already in the room
+        fn load(&mut self, registry: &Registry, coords: chunk::Coords) {
+            self.unloaded.remove(&coords);
+            let mins = coords.block_mins();
+            let chunk: Chunk = SpaceIter::new(V3::zeros(), V3::repeat(chunk::DIM))
+                .map(|offset| self.block(mins + offset).unwrap())
+                .collect();
+            light_chunk(self, registry, coords, &Survey::new(registry, &chunk));
+        }
+
+        /// Lights the room from nothing, to check incremental updates against
+        fn relit(&self, registry: &Registry) -> HashMap<BlockCoords, u8> {
+            let mut room = Room::new(self.size);
+            room.blocks = self.blocks.clone();
+
+            let mut sky = VecDeque::new();
+            for xy in columns(0, self.size) {
+                let height = (0 .. self.size).rev()
+                    .find(|z| !lets_through(registry, room.block(at(xy.x, xy.y, *z)).unwrap()))
+                    .unwrap_or(i32::MIN);
+                room.heights.set(xy, height);
+
+                for z in (height + 1).max(0) .. self.size {
+                    room.set_light(at(xy.x, xy.y, z), Channel::Sky, MAX_LEVEL);
+                    sky.push_back(at(xy.x, xy.y, z));
+                }
+            }
+
+            let mut lamps = VecDeque::new();
+            for (at, block) in &self.blocks {
+                let level = registry.get(*block).light;
+                if level > 0 {
+                    room.set_light(*at, Channel::Block, level);
+                    lamps.push_back(*at);
+                }
+            }
+
+            spread(&mut room, registry, Channel::Sky, sky);
+            spread(&mut room, registry, Channel::Block, lamps);
+            room.lit()
+        }
+
+        fn lit(&self) -> HashMap<BlockCoords, u8> {
+            let mut light = self.light.clone();
+            light.retain(|_, packed| *packed > 0);
+            light
+        }
+    }
+
+    impl World for Room {
+        fn block(&self, at: BlockCoords) -> Option<Block> {
+            if self.contains(at) && !self.unloaded.contains(&at.chunk()) {
+                Some(*self.blocks.get(&at).unwrap_or(&Block::EMPTY))
+            }
+            else {
+                None
+            }
+        }
+
+        fn packed_light(&self, at: BlockCoords) -> u8 {
+            *self.light.get(&at).unwrap_or(&0)
+        }
+
+        fn set_packed_light(&mut self, at: BlockCoords, packed: u8) {
+            if self.block(at).is_some() {
+                self.light.insert(at, packed);
+            }
+        }
+
+        fn heights(&self) -> &Heights {
+            &self.heights
+        }
+
+        fn heights_mut(&mut self) -> &mut Heights {
+            &mut self.heights
+        }
+
+        fn bottom(&self) -> i32 {
+            0
+        }
+    }
+
+    fn at(x: i32, y: i32, z: i32) -> BlockCoords {
+        BlockCoords::new(P3::new(x, y, z))
+    }
+
+    #[test]
+    fn packing() {
+        let packed = Channel::Sky.with(Channel::Block.with(0, 9), 12);
+        assert_eq!(Channel::Block.of(packed), 9);
+        assert_eq!(Channel::Sky.of(packed), 12);
+        assert_eq!(Channel::Block.of(Channel::Block.with(packed, 3)), 3);
+        assert_eq!(Channel::Sky.of(Channel::Block.with(packed, 3)), 12);
+
+        let mut levels = Levels::new_dark();
+        levels.set(V3::new(1, 2, 3), packed);
+        assert_eq!(levels.get(V3::new(1, 2, 3)), packed);
+        assert_eq!(levels.get(V3::new(3, 2, 1)), 0);
+        levels.set(V3::new(1, 2, 3), 0);
+        levels.compact();
+        assert!(matches!(levels.storage, Storage::Uniform(0)));
+    }
+
+    #[test]
+    fn light_falls_off_with_distance() {
+        let registry = registry();
+        let mut room = Room::open(&registry, 32);
+        room.place(&registry, at(10, 10, 10), LAMP);
+
+        let light = |at| room.light(at, Channel::Block);
+        let level = registry.get(LAMP).light;
+        assert_eq!(light(at(10, 10, 10)), level);
+        assert_eq!(light(at(11, 10, 10)), level - 1);
+        assert_eq!(light(at(12, 11, 10)), level - 3);
+        assert_eq!(light(at(10, 10, 10 + level as i32 - 1)), 1);
+        assert_eq!(light(at(10, 10, 10 + level as i32)), 0);
+    }
+
+    #[test]
+    fn walls_cast_shadows_and_removal_darkens() {
+        let registry = registry();
+        let mut room = Room::open(&registry, 16);
+        for y in 0 .. 16 {
+            for z in 0 .. 16 {
+                room.place(&registry, at(8, y, z), STONE);
+            }
+        }
+
+        room.place(&registry, at(4, 8, 8), LAMP);
+        assert!(room.light(at(7, 8, 8), Channel::Block) > 0);
+        assert_eq!(room.light(at(9, 8, 8), Channel::Block), 0);
+
+        // a doorway lets light round the wall
+        room.place(&registry, at(8, 8, 8), Block::EMPTY);
+        let through = room.light(at(9, 8, 8), Channel::Block);
+        assert!(through > 0);
+        assert!(room.light(at(9, 12, 8), Channel::Block) < through);
+        assert_eq!(room.lit(), room.relit(&registry));
+
+        // closing it puts the far side back in the dark
+        room.place(&registry, at(8, 8, 8), SOIL);
+        assert_eq!(room.light(at(9, 8, 8), Channel::Block), 0);
+        assert_eq!(room.lit(), room.relit(&registry));
+
+        room.place(&registry, at(4, 8, 8), Block::EMPTY);
+        assert!(room.lit().values().all(|packed| Channel::Block.of(*packed) == 0));
+    }
+
+    #[test]
+    fn overlapping_lights() {
+        let registry = registry();
+        let mut room = Room::open(&registry, 32);
+        let lamps = [at(4, 4, 4), at(9, 5, 4), at(12, 12, 12), at(5, 18, 6)];
+        for &lamp in &lamps {
+            room.place(&registry, lamp, LAMP);
+        }
+        room.place(&registry, at(7, 4, 4), GRASS);
+        room.place(&registry, at(7, 5, 4), GRASS);
+        assert_eq!(room.lit(), room.relit(&registry));
+
+        // removing one leaves the others' light as it would be without it
+        room.place(&registry, lamps[1], Block::EMPTY);
+        assert_eq!(room.lit(), room.relit(&registry));
+        room.place(&registry, at(7, 4, 4), Block::EMPTY);
+        assert_eq!(room.lit(), room.relit(&registry));
+
+        // a lamp replacing a lit block
+        room.place(&registry, at(4, 5, 4), LAMP);
+        assert_eq!(room.lit(), room.relit(&registry));
+        room.place(&registry, lamps[0], STONE);
+        assert_eq!(room.lit(), room.relit(&registry));
+    }
+
+    /// Columns in the square from `min` to `max` along X and Y
+    fn columns(min: i32, max: i32) -> impl Iterator<Item = V2i32> {
+        SpaceIter::new(V3::new(min, min, 0), V3::new(max, max, 1)).map(|xyz| xyz.xy())
+    }
+
+    /// Ground with a roof over part of it
+    fn shelter(registry: &Registry, room: &mut Room) {
+        for xy in columns(0, room.size) {
+            for z in 0 .. 4 {
+                room.place(registry, at(xy.x, xy.y, z), STONE);
+            }
+        }
+        for xy in columns(8, 24) {
+            room.place(registry, at(xy.x, xy.y, 10), SOIL);
+        }
+    }
+
+    #[test]
+    fn sky_light_reaches_under_overhangs() {
+        let registry = registry();
+        let mut room = Room::open(&registry, 32);
+        shelter(&registry, &mut room);
+
+        let sky = |room: &Room, at| room.light(at, Channel::Sky);
+        assert_eq!(sky(&room, at(4, 16, 5)), MAX_LEVEL);
+        assert_eq!(sky(&room, at(4, 16, 2)), 0);
+        assert_eq!(sky(&room, at(8, 16, 5)), MAX_LEVEL - 1);
+        assert_eq!(sky(&room, at(16, 16, 5)), MAX_LEVEL - 8);
+        assert_eq!(sky(&room, at(16, 16, 11)), MAX_LEVEL);
+        assert_eq!(room.heights.get(V2::new(16, 16)), 10);
+        assert_eq!(room.lit(), room.relit(&registry));
+
+        // a hole in the roof lets the sky straight down
+        room.place(&registry, at(16, 16, 10), Block::EMPTY);
+        assert_eq!(room.heights.get(V2::new(16, 16)), 3);
+        assert_eq!(sky(&room, at(16, 16, 4)), MAX_LEVEL);
+        assert_eq!(sky(&room, at(17, 16, 4)), MAX_LEVEL - 1);
+        assert_eq!(room.lit(), room.relit(&registry));
+
+        // and down a shaft dug through the ground
+        for z in (0 .. 4).rev() {
+            room.place(&registry, at(16, 16, z), Block::EMPTY);
+        }
+        assert_eq!(room.heights.get(V2::new(16, 16)), i32::MIN);
+        assert_eq!(sky(&room, at(16, 16, 0)), MAX_LEVEL);
+        assert_eq!(room.lit(), room.relit(&registry));
+
+        // stopping it up again shades everything below
+        room.place(&registry, at(16, 16, 12), GRASS);
+        assert_eq!(room.heights.get(V2::new(16, 16)), 12);
+        assert!(sky(&room, at(16, 16, 0)) < MAX_LEVEL - 8);
+        assert_eq!(room.lit(), room.relit(&registry));
+    }
+
+    #[test]
+    fn chunks_light_as_they_load() {
+        let registry = registry();
+        let mut template = Room::new(3 * chunk::DIM);
+        shelter(&registry, &mut template);
+        template.blocks.insert(at(20, 20, 6), LAMP);
+        template.blocks.insert(at(30, 3, 40), LAMP);
+        for z in 0 .. 40 {
+            template.blocks.insert(at(40, 40, z), TREE_TRUNK);
+        }
+
+        let chunks = template.chunks();
+        let orders: Vec<Vec<chunk::Coords>> = vec![
+            chunks.clone(),
+            chunks.iter().rev().copied().collect(),
+            (0 .. chunks.len()).map(|i| chunks[(i * 7) % chunks.len()]).collect(),
+        ];
+
+        for order in orders {
+            let mut room = Room::new(template.size);
+            room.blocks = template.blocks.clone();
+            room.unloaded = chunks.iter().copied().collect();
+            for coords in order {
+                room.load(&registry, coords);
+            }
+            assert_eq!(room.lit(), room.relit(&registry));
+        }
+    }
+}
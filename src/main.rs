@@ -6,19 +6,29 @@ mod chunk_cache;
 mod chunk_maker;
 mod chunk_source;
 mod chunk_store;
+mod clock;
 mod game;
 mod gl;
 mod halton;
+mod journal;
+mod light;
 mod math;
 mod mesher;
+mod palette;
+mod schematic;
 mod shader;
+mod sky;
 mod stage;
 mod texture;
+mod workers;
+mod world_edit;
 
 use {
     crate::{
+        clock::Command,
         gl::types::*,
         math::*,
+        world_edit::Tool,
     },
     std::{
         error::Error,
@@ -67,6 +77,7 @@ type Context = glutin::WindowedContext<glutin::PossiblyCurrent>;
 struct App {
     ctx:                    Context,
     ticks_since_prev_frame: u32,
+    frames_since_title:     u32,
     screen_dims:            V2,
     focused:                bool,
     inputs:                 game::Inputs,
@@ -78,6 +89,7 @@ impl App {
         let app = App {
             ctx,
             ticks_since_prev_frame: 0,
+            frames_since_title: 0,
             screen_dims: V2::new(500., 500.),
             focused: false,
             inputs: game::Inputs::new(),
@@ -121,6 +133,35 @@ impl App {
                         VK::LControl => self.inputs.down  = down,
                         VK::LShift   => self.inputs.fast  = down,
                         VK::Z        => self.inputs.zoom  = down,
+                        VK::F        => self.inputs.fly   = down,
+                        VK::U        => self.inputs.undo  = down,
+                        VK::Y        => self.inputs.redo  = down,
+
+                        VK::Key1 | VK::Key2 | VK::Key3 | VK::Key4 | VK::Key5 |
+                        VK::Key6 | VK::Key7 | VK::Key8 | VK::Key9 if down => {
+                            self.inputs.select = Some(*vk as usize - VK::Key1 as usize);
+                        }
+
+                        VK::X        if down => self.inputs.variant += 1,
+
+                        VK::LBracket if down => self.inputs.tool = Some(Tool::MarkFirst),
+                        VK::RBracket if down => self.inputs.tool = Some(Tool::MarkSecond),
+                        VK::G        if down => self.inputs.tool = Some(Tool::Fill),
+                        VK::R        if down => self.inputs.tool = Some(Tool::Replace),
+                        VK::H        if down => self.inputs.tool = Some(Tool::Hollow),
+                        VK::B        if down => self.inputs.tool = Some(Tool::Sphere),
+                        VK::C        if down => self.inputs.tool = Some(Tool::Cylinder),
+                        VK::K        if down => self.inputs.tool = Some(Tool::Copy),
+                        VK::V        if down => self.inputs.tool = Some(Tool::Paste),
+                        VK::T        if down => self.inputs.tool = Some(Tool::Rotate),
+                        VK::M        if down => self.inputs.tool = Some(Tool::Mirror),
+                        VK::F5       if down => self.inputs.tool = Some(Tool::SaveSchematic),
+                        VK::F9       if down => self.inputs.tool = Some(Tool::LoadSchematic),
+
+                        VK::N        if down => self.inputs.clock = Some(Command::NextQuarter),
+                        VK::P        if down => self.inputs.clock = Some(Command::ToggleFrozen),
+                        VK::Home     if down => self.inputs.clock = Some(Command::Set(clock::NOON)),
+
                         _ => { }
                     }
                 }
@@ -129,12 +170,23 @@ impl App {
                     let down = *state == event::ElementState::Pressed;
                     use event::MouseButton::*;
                     match button {
-                        Left  => self.inputs.smash = down,
-                        Right => self.inputs.build = down,
-                        _     => { }
+                        Left   => self.inputs.smash = down,
+                        Right  => self.inputs.build = down,
+                        Middle => self.inputs.pick  = down,
+                        _      => { }
                     }
                 }
 
+                event::WindowEvent::MouseWheel { delta, .. } => {
+                    let y = match delta {
+                        event::MouseScrollDelta::LineDelta(_, y)    => *y,
+                        event::MouseScrollDelta::PixelDelta(offset) => offset.y as f32,
+                    };
+                    // scrolling down moves on to the next block
+                    if y < 0. { self.inputs.scroll += 1; }
+                    if y > 0. { self.inputs.scroll -= 1; }
+                }
+
                 _ => { }
             }
 
@@ -165,6 +217,25 @@ impl App {
             self.ticks_since_prev_frame = 0;
             self.game.draw(self.screen_dims);
             self.ctx.swap_buffers().unwrap();
+
+            self.frames_since_title += 1;
+            if self.frames_since_title == FRAME_RATE as u32 {
+                self.frames_since_title = 0;
+                let target = match self.game.target() {
+                    Some((def, distance)) => format!(" - looking at {} {:.1} away", def.name, distance),
+                    None                  => String::new(),
+                };
+                let title = format!(
+                    "voxels - {} - holding {}{} - {}, {} loading - cache: {}",
+                    self.game.clock(),
+                    self.game.held_block().name,
+                    target,
+                    self.game.draw_stats(),
+                    self.game.chunks_loading(),
+                    self.game.cache_stats()
+                );
+                self.ctx.window().set_title(&title);
+            }
         }
     }
 }
@@ -236,6 +307,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             WindowEvent { event: win_event, .. } => {
                 use event::WindowEvent::*;
                 if *win_event == CloseRequested {
+                    app.game.shut_down();
                     *flow = ControlFlow::Exit;
                 }
                 else {
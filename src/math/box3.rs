@@ -26,6 +26,14 @@ impl Box3 {
         Self::new_unchecked(mins, mins + dims)
     }
 
+    pub fn mins(&self) -> P3 {
+        self.mins
+    }
+
+    pub fn maxs(&self) -> P3 {
+        self.maxs
+    }
+
     #[must_use]
     pub fn dilate(&self, with: &Box3) -> Box3 {
         Self::new_unchecked(self.mins - with.maxs.coords, self.maxs - with.mins.coords)
@@ -43,6 +51,10 @@ impl Box3 {
     }
 }
 
+/// How far a point may have sunk into a box and still be taken to be on its
+/// surface, absorbing rounding in earlier moves
+pub const SKIN: f32 = 0.001;
+
 #[derive(Clone, Copy)]
 pub struct Intersection {
     pub lambda: f32,
@@ -53,39 +65,53 @@ pub struct Intersection {
 impl<L> Intersect<L> for Box3 where L: Linear {
     type Intersection = Intersection;
 
+    /// The first point at which a line enters the box
+    ///
+    /// A source already inside the box by more than `SKIN` finds nothing, so
+    /// that whatever is caught inside can leave. One within `SKIN` of a face,
+    /// moving inward, enters at once, and one moving along a face only
+    /// grazes it.
     fn intersect(&self, other: &L) -> Option<Intersection> {
-        let p = other.source();
-        let rs = other.stride().map(|x| 1. / x);
-
-        let pmins = (self.mins - p).component_mul(&rs);
-        let pmaxs = (self.maxs - p).component_mul(&rs);
-
-        let tmins = pmins.zip_map(&pmaxs, |a, b| a.min(b));
-        let tmaxs = pmins.zip_map(&pmaxs, |a, b| a.max(b));
-
-        let tmin = tmins.max();
-        let tmax = tmaxs.min();
-
-        // TODO check correctness
-        let normal = match tmin {
-            t if t == pmins.x => -V3::x(),
-            t if t == pmaxs.x =>  V3::x(),
-            t if t == pmins.y => -V3::y(),
-            t if t == pmaxs.y =>  V3::y(),
-            t if t == pmins.z => -V3::z(),
-            t if t == pmaxs.z =>  V3::z(),
-            _ => unreachable!()
-        };
-
-        // TODO deal with ntersections when source is inside box
-        if tmax <= tmin || !other.parameter_on(tmin) {
+        let (source, stride) = (other.source(), other.stride());
+
+        let mut enter = (f32::NEG_INFINITY, 0);
+        let mut exit = f32::INFINITY;
+
+        for i in 0 .. 3 {
+            if stride[i] == 0. {
+                if source[i] <= self.mins[i] + SKIN || source[i] >= self.maxs[i] - SKIN {
+                    return None;
+                }
+                continue;
+            }
+
+            let a = (self.mins[i] - source[i]) / stride[i];
+            let b = (self.maxs[i] - source[i]) / stride[i];
+            let (near, far) = if a < b { (a, b) } else { (b, a) };
+
+            if near > enter.0 {
+                enter = (near, i);
+            }
+            exit = exit.min(far);
+        }
+
+        let (lambda, axis) = enter;
+        if lambda == f32::NEG_INFINITY || exit <= lambda || exit <= 0. {
             return None;
         }
 
-        let lambda = tmin;
-        let point = other.at(tmin);
+        if lambda < 0. && -lambda * stride[axis].abs() > SKIN {
+            return None;
+        }
+
+        let lambda = lambda.max(0.);
+        if !other.parameter_on(lambda) {
+            return None;
+        }
 
-        Some(Intersection { lambda, point, normal })
+        let mut normal = V3::zeros();
+        normal[axis] = -stride[axis].signum();
+        Some(Intersection { lambda, point: other.at(lambda), normal })
     }
 }
 
@@ -135,6 +161,15 @@ mod tests {
         b.intersect(&Segment::new(P3::new(-1.8, 0., 0.), V3::new(2., 2., 0.)))
             .map(|ixn| assert_eq!(ixn.normal, V3::new(-1., 0., 0.)))
             .unwrap();
+
+        // starting inside finds nothing, unless only just inside
+        assert!(b.intersect(&Segment::new(P3::new(0., 0., 0.), V3::new(2., 0., 0.))).is_none());
+        b.intersect(&Segment::new(P3::new(-0.9995, 0., 0.), V3::new(2., 0., 0.)))
+            .map(|ixn| assert_eq!(ixn.lambda, 0.))
+            .unwrap();
+
+        // sliding along a face
+        assert!(b.intersect(&Segment::new(P3::new(-2., 1., 0.), V3::new(3., 0., 0.))).is_none());
     }
 
     #[test]
@@ -0,0 +1,299 @@
+
+use {
+    super::*,
+    box3::SKIN,
+};
+
+/// The outcome of resolving a motion
+#[derive(Clone, Copy, Debug)]
+pub struct Resolution {
+    /// How far the hitbox may actually move
+    pub motion:  V3,
+    /// Whether the hitbox ended up standing on something
+    pub floor:   bool,
+    pub ceiling: bool,
+    pub wall:    bool,
+}
+
+/// Moves an axis-aligned hitbox through a set of solid boxes
+///
+/// A hitbox which starts out overlapping solids, such as when a block is
+/// placed where it stands, is first pushed out of each the shortest way.
+/// Motion slides along whatever it strikes: the component into each surface
+/// hit is removed and the rest of the motion continues, for at most
+/// `max_iterations` contacts. A move which is blocked by a wall while the
+/// hitbox is standing on something is retried raised by up to `step_height`,
+/// so that ledges no higher than that are climbed without jumping.
+#[derive(Clone, Copy, Debug)]
+pub struct Resolver {
+    pub step_height:    f32,
+    pub max_iterations: usize,
+}
+
+impl Resolver {
+    pub fn new(step_height: f32) -> Resolver {
+        Resolver { step_height, max_iterations: 4 }
+    }
+
+    /// Resolves moving `hitbox`, placed at `position`, by `motion`
+    pub fn resolve(&self, hitbox: &Box3, position: P3, motion: V3, solids: &[Box3])
+        -> Resolution
+    {
+        // sweeping the hitbox through the solids is the same as sweeping its
+        // origin through the solids grown by the hitbox
+        let grown: Vec<Box3> = solids.iter()
+            .map(|solid| solid.dilate(hitbox))
+            .collect();
+
+        let pushed = self.push_out(&grown, position, motion);
+        let mut resolution = self.resolve_free(&grown, position + pushed, motion);
+        resolution.motion += pushed;
+        resolution
+    }
+
+    /// Moves the origin out of the grown solids it is inside, through
+    /// whichever of their faces is nearest and leads clear of all of them,
+    /// preferring those it is already moving toward
+    fn push_out(&self, grown: &[Box3], position: P3, motion: V3) -> V3 {
+        let inside = |at: P3, target: &Box3| {
+            (0 .. 3).all(|i| at[i] > target.mins()[i] + SKIN && at[i] < target.maxs()[i] - SKIN)
+        };
+
+        let mut at = position;
+        for _ in 0 .. self.max_iterations {
+            let mut exits: Vec<V3> = grown.iter()
+                .filter(|target| inside(at, target))
+                .flat_map(|target| (0 .. 3).flat_map(move |i| {
+                    let mut down = V3::zeros();
+                    down[i] = target.mins()[i] - at[i];
+                    let mut up = V3::zeros();
+                    up[i] = target.maxs()[i] - at[i];
+                    vec![down, up]
+                }))
+                .collect();
+            if exits.is_empty() {
+                break;
+            }
+
+            exits.sort_by_key(|exit| (OrdFloat(exit.norm()), OrdFloat(-exit.dot(&motion))));
+            let clear = exits.iter()
+                .find(|&&exit| !grown.iter().any(|target| inside(at + exit, target)));
+
+            // with no way straight out, take the nearest and look again
+            at += *clear.unwrap_or(&exits[0]);
+        }
+        at - position
+    }
+
+    fn resolve_free(&self, grown: &[Box3], position: P3, motion: V3) -> Resolution {
+        let direct = self.slide(grown, position, motion);
+        if self.step_height <= 0. || !(direct.wall && direct.floor) {
+            return direct;
+        }
+
+        let up = self.slide(grown, position, V3::z() * self.step_height);
+        let flat = V3::new(motion.x, motion.y, 0.);
+        let across = self.slide(grown, position + up.motion, flat);
+        let down = self.slide(
+            grown,
+            position + up.motion + across.motion,
+            V3::z() * (motion.z.min(0.) - up.motion.z)
+        );
+
+        let stepped = Resolution {
+            motion:  up.motion + across.motion + down.motion,
+            floor:   down.floor,
+            ceiling: false,
+            wall:    across.wall,
+        };
+
+        if stepped.floor && stepped.motion.xy().norm() > direct.motion.xy().norm() + SKIN {
+            stepped
+        }
+        else {
+            direct
+        }
+    }
+
+    fn slide(&self, grown: &[Box3], position: P3, motion: V3) -> Resolution {
+        let mut result = Resolution {
+            motion:  V3::zeros(),
+            floor:   false,
+            ceiling: false,
+            wall:    false,
+        };
+        let mut at = position;
+        let mut remaining = motion;
+
+        for _ in 0 .. self.max_iterations {
+            if remaining.norm_squared() == 0. {
+                break;
+            }
+
+            let path = Segment::new(at, remaining);
+            let nearest = grown.iter()
+                .filter_map(|target| target.intersect(&path))
+                .min_by_key(|contact| OrdFloat(contact.lambda));
+
+            let contact = match nearest {
+                Some(contact) => contact,
+                None => {
+                    at += remaining;
+                    break;
+                }
+            };
+
+            at = contact.point;
+            remaining *= 1. - contact.lambda;
+            remaining -= contact.normal * contact.normal.dot(&remaining);
+
+            match contact.normal.z {
+                z if z > 0.5  => { result.floor = true; }
+                z if z < -0.5 => { result.ceiling = true; }
+                _             => { result.wall = true; }
+            }
+        }
+
+        result.motion = at - position;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hitbox() -> Box3 {
+        Box3::with_dims(P3::new(-0.4, -0.4, 0.), V3::new(0.8, 0.8, 1.6))
+    }
+
+    fn block(x: i32, y: i32, z: i32) -> Box3 {
+        Box3::with_dims(P3::new(x as f32, y as f32, z as f32), V3::repeat(1.))
+    }
+
+    /// A 7x7 floor at z = 0 around the origin, plus the given blocks
+    fn floor_and(extra: &[(i32, i32, i32)]) -> Vec<Box3> {
+        let mut solids: Vec<Box3> = SpaceIter::new(V3::new(-3, -3, 0), V3::new(4, 4, 1))
+            .map(|c| block(c.x, c.y, c.z))
+            .collect();
+        solids.extend(extra.iter().map(|&(x, y, z)| block(x, y, z)));
+        solids
+    }
+
+    fn assert_near(a: V3, b: V3) {
+        assert!((a - b).norm() < 0.01, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn lands_and_rests() {
+        let solids = floor_and(&[]);
+        let resolver = Resolver::new(0.);
+
+        let fall = resolver.resolve(&hitbox(), P3::new(0.5, 0.5, 1.5), V3::new(0., 0., -1.), &solids);
+        assert_near(fall.motion, V3::new(0., 0., -0.5));
+        assert!(fall.floor);
+
+        // sunk very slightly into the floor, which still holds
+        let rest = resolver.resolve(&hitbox(), P3::new(0.5, 0.5, 0.9999), V3::new(0.3, 0., -0.1), &solids);
+        assert_near(rest.motion, V3::new(0.3, 0., 0.));
+        assert!(rest.floor);
+    }
+
+    #[test]
+    fn slides_along_a_wall() {
+        let solids = floor_and(&[(2, -1, 1), (2, 0, 1), (2, 1, 1)]);
+        let resolver = Resolver::new(0.);
+
+        let result = resolver.resolve(&hitbox(), P3::new(0.5, 0.5, 1.), V3::new(2., 0.5, 0.), &solids);
+        assert_near(result.motion, V3::new(1.1, 0.5, 0.));
+        assert!(result.wall);
+    }
+
+    #[test]
+    fn stops_in_a_corner() {
+        let solids = floor_and(&[
+            (2, -1, 1), (2, 0, 1), (2, 1, 1), (2, 2, 1),
+            (-1, 2, 1), (0, 2, 1), (1, 2, 1),
+        ]);
+        let resolver = Resolver::new(0.);
+
+        let start = P3::new(0.5, 0.5, 1.);
+        let result = resolver.resolve(&hitbox(), start, V3::new(3., 2., -0.1), &solids);
+        assert_near(result.motion, V3::new(1.1, 1.1, 0.));
+
+        // pushing further into the corner goes nowhere
+        let again = resolver.resolve(&hitbox(), start + result.motion, V3::new(1., 1., -0.1), &solids);
+        assert_near(again.motion, V3::zeros());
+        assert!(again.floor && again.wall);
+    }
+
+    #[test]
+    fn follows_a_corridor() {
+        // a one-block-wide corridor along x, barely wider than the hitbox
+        let mut walls = Vec::new();
+        for x in -3 .. 4 {
+            walls.push((x, -1, 1));
+            walls.push((x,  1, 1));
+        }
+        let solids = floor_and(&walls);
+        let resolver = Resolver::new(0.);
+
+        let result = resolver.resolve(&hitbox(), P3::new(-2., 0.5, 1.), V3::new(3., 0.4, 0.), &solids);
+        assert_near(result.motion, V3::new(3., 0.1, 0.));
+    }
+
+    #[test]
+    fn steps_up_a_ledge() {
+        let resolver = Resolver::new(1.1);
+        let start = P3::new(0.5, 0.5, 1.);
+        let motion = V3::new(1., 0., -0.05);
+
+        let ledge = floor_and(&[(1, 0, 1), (2, 0, 1)]);
+        let result = resolver.resolve(&hitbox(), start, motion, &ledge);
+        assert_near(result.motion, V3::new(1., 0., 1.));
+        assert!(result.floor);
+
+        // two blocks is too high
+        let cliff = floor_and(&[(1, 0, 1), (2, 0, 1), (1, 0, 2), (2, 0, 2)]);
+        let result = resolver.resolve(&hitbox(), start, motion, &cliff);
+        assert_near(result.motion, V3::new(0.1, 0., 0.));
+
+        // and nothing is climbed in mid-air
+        let result = resolver.resolve(&hitbox(), start + V3::z() * 0.5, motion, &ledge);
+        assert!(result.motion.z < 0.);
+    }
+
+    #[test]
+    fn escapes_from_overlap() {
+        let solids = floor_and(&[(0, 0, 1)]);
+        let resolver = Resolver::new(0.);
+
+        // embedded in a block, as if one was placed on top of us, and pushed
+        // out of its nearest side, the way we were going
+        let result = resolver.resolve(&hitbox(), P3::new(0.5, 0.5, 1.), V3::new(1., 0., 0.), &solids);
+        assert_near(result.motion, V3::new(1.9, 0., 0.));
+
+        // sunk into the floor, the way out is up
+        let result = resolver.resolve(&hitbox(), P3::new(0.5, 0.5, 0.7), V3::zeros(), &floor_and(&[]));
+        assert_near(result.motion, V3::new(0., 0., 0.3));
+
+        // wedged between two blocks, out past both of them
+        let solids = floor_and(&[(0, 0, 1), (1, 0, 1)]);
+        let start = P3::new(1., 0.5, 1.);
+        let result = resolver.resolve(&hitbox(), start, V3::zeros(), &solids);
+        let end = hitbox().at(start + result.motion);
+        assert!(solids.iter().all(|solid| {
+            (0 .. 3).any(|i| end.maxs()[i] <= solid.mins()[i] + SKIN || end.mins()[i] >= solid.maxs()[i] - SKIN)
+        }));
+    }
+
+    #[test]
+    fn iterations_are_bounded() {
+        let solids = floor_and(&[]);
+        let resolver = Resolver { step_height: 0., max_iterations: 1 };
+
+        // the first contact uses up the only iteration
+        let result = resolver.resolve(&hitbox(), P3::new(0.5, 0.5, 1.5), V3::new(1., 0., -1.), &solids);
+        assert_near(result.motion, V3::new(0.5, 0., -0.5));
+    }
+}
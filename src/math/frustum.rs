@@ -0,0 +1,143 @@
+
+use super::*;
+
+/// Where a box lies relative to a frustum
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+/// The volume visible through a projection, as six inward-facing planes:
+/// left, right, bottom, top, near and far, in that order
+///
+/// Each plane is held as `(a, b, c, d)` with a unit normal `(a, b, c)`, so
+/// that `a x + b y + c z + d` is the signed distance of a point from it.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [V4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of the clip volume from a matrix taking points
+    /// into GL clip space, where visible points have each of x, y and z
+    /// between -w and w
+    pub fn from_clip_matrix(to_clip: &M4) -> Frustum {
+        let row = |i: usize| to_clip.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z]
+            .map(|plane| plane / plane.xyz().norm());
+
+        Frustum { planes }
+    }
+
+    /// Classifies a box against each plane in turn
+    ///
+    /// This is conservative: a box near a corner of the frustum may be found
+    /// to intersect it while lying wholly outside.
+    pub fn classify(&self, b: &Box3) -> Containment {
+        let (mins, maxs) = (b.mins(), b.maxs());
+        let mut result = Containment::Inside;
+
+        for plane in &self.planes {
+            let corner = |toward: bool| {
+                let pick = |i: usize| if (plane[i] >= 0.) == toward { maxs[i] } else { mins[i] };
+                V4::new(pick(0), pick(1), pick(2), 1.)
+            };
+
+            if plane.dot(&corner(true)) < 0. {
+                return Containment::Outside;
+            }
+
+            if plane.dot(&corner(false)) < 0. {
+                result = Containment::Intersecting;
+            }
+        }
+
+        result
+    }
+
+    /// Whether any part of the box may be visible
+    pub fn may_see(&self, b: &Box3) -> bool {
+        self.classify(b) != Containment::Outside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Looking down -Z from the origin, with a 90 degree field of view
+    fn frustum() -> Frustum {
+        let projection = Perspective::new(1., PI / 2., 1., 100.);
+        Frustum::from_clip_matrix(projection.as_matrix())
+    }
+
+    fn assert_near(a: V4, b: V4) {
+        assert!((a - b).norm() < 0.001, "{} != {}", a, b);
+    }
+
+    fn contains(frustum: &Frustum, point: P3) -> bool {
+        let point = point.to_homogeneous();
+        frustum.planes.iter()
+            .all(|plane| plane.dot(&point) >= 0.)
+    }
+
+    #[test]
+    fn planes() {
+        let frustum = frustum();
+        let planes = frustum.planes;
+        let h = 0.5f32.sqrt();
+
+        assert_near(planes[0], V4::new( h,  0., -h,  0.));
+        assert_near(planes[1], V4::new(-h,  0., -h,  0.));
+        assert_near(planes[2], V4::new( 0.,  h, -h,  0.));
+        assert_near(planes[3], V4::new( 0., -h, -h,  0.));
+        assert_near(planes[4], V4::new( 0.,  0., -1., -1.));
+        assert_near(planes[5], V4::new( 0.,  0.,  1., 100.));
+
+        assert!(contains(&frustum, P3::new(0., 0., -2.)));
+        assert!(contains(&frustum, P3::new(4., -4., -5.)));
+        assert!(!contains(&frustum, P3::new(0., 0., 2.)));
+        assert!(!contains(&frustum, P3::new(0., 0., -0.5)));
+        assert!(!contains(&frustum, P3::new(0., 0., -101.)));
+        assert!(!contains(&frustum, P3::new(6., 0., -5.)));
+    }
+
+    #[test]
+    fn classify() {
+        let frustum = frustum();
+        let unit_at = |x, y, z| Box3::with_dims(P3::new(x, y, z), V3::repeat(1.));
+
+        assert_eq!(frustum.classify(&unit_at(-0.5, -0.5, -10.)), Containment::Inside);
+        assert_eq!(frustum.classify(&unit_at(-0.5, -0.5,   5.)), Containment::Outside);
+        assert_eq!(frustum.classify(&unit_at(20.,   0.,  -10.)), Containment::Outside);
+        assert_eq!(frustum.classify(&unit_at(-0.5, -0.5, -200.)), Containment::Outside);
+
+        // straddling the left plane, and the near plane
+        assert_eq!(frustum.classify(&unit_at(-10.5, 0., -10.)), Containment::Intersecting);
+        assert_eq!(frustum.classify(&unit_at(-0.5, -0.5, -1.5)), Containment::Intersecting);
+
+        // enclosing the whole frustum
+        let huge = Box3::with_dims(P3::new(-500., -500., -500.), V3::repeat(1000.));
+        assert_eq!(frustum.classify(&huge), Containment::Intersecting);
+    }
+
+    #[test]
+    fn follows_the_view() {
+        let projection = Perspective::new(1., PI / 2., 0.1, 100.);
+        let view = Motion::look_at_rh(
+            &P3::new(10., 0., 0.),
+            &P3::new(20., 0., 0.),
+            &V3::z()
+        );
+        let frustum = Frustum::from_clip_matrix(&(projection.as_matrix() * view.to_homogeneous()));
+
+        let unit_at = |x, y, z| Box3::with_dims(P3::new(x, y, z), V3::repeat(1.));
+        assert!(frustum.may_see(&unit_at(15., 0., 0.)));
+        assert!(!frustum.may_see(&unit_at(5., 0., 0.)));
+        assert!(!frustum.may_see(&unit_at(15., 0., 10.)));
+    }
+}
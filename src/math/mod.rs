@@ -11,6 +11,15 @@ pub use planar::Plane;
 pub mod box3;
 pub use box3::Box3;
 
+pub mod frustum;
+pub use frustum::Frustum;
+
+pub mod traversal;
+pub use traversal::Traversal;
+
+pub mod collision;
+pub use collision::Resolver;
+
 pub use nalgebra as na;
 
 pub trait Intersect<With> {
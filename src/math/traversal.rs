@@ -0,0 +1,165 @@
+
+use super::*;
+
+/// A unit cell entered by a `Traversal`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Crossing {
+    pub cell:   V3i32,
+    /// The normal of the face through which the cell was entered, pointing
+    /// back toward the previous cell; zero for the starting cell
+    pub normal: V3i32,
+    /// The line parameter at which the cell was entered
+    pub lambda: f32,
+}
+
+/// Iterates over the unit cells a line passes through, in order
+///
+/// This is the voxel traversal of Amanatides and Woo: it steps to whichever
+/// neighbouring cell boundary the line reaches first, so each cell costs a
+/// few comparisons. The iteration ends once the line parameter leaves the
+/// range accepted by the `Linear`, so it is endless for a `Line` or `Ray`.
+pub struct Traversal<L> {
+    linear:  L,
+    cell:    V3i32,
+    step:    V3i32,
+    /// Parameter at which the next boundary along each axis is reached
+    t_max:   V3,
+    /// Parameter distance between boundaries along each axis
+    t_delta: V3,
+    next:    Option<Crossing>,
+}
+
+impl<L> Traversal<L> where L: Linear {
+    pub fn new(linear: L) -> Traversal<L> {
+        let source = linear.source();
+        let stride = linear.stride();
+        let cell = source.coords.map(|x| x.floor() as i32);
+
+        let step = stride.map(|x| {
+            if      x > 0. {  1 }
+            else if x < 0. { -1 }
+            else           {  0 }
+        });
+
+        let mut t_max = V3::repeat(f32::INFINITY);
+        let mut t_delta = V3::repeat(f32::INFINITY);
+        for i in 0 .. 3 {
+            if step[i] != 0 {
+                let boundary = (cell[i] + (step[i] > 0) as i32) as f32;
+                t_max[i] = (boundary - source[i]) / stride[i];
+                t_delta[i] = 1. / stride[i].abs();
+            }
+        }
+
+        let next = Some(Crossing { cell, normal: V3::zeros(), lambda: 0. });
+        Traversal { linear, cell, step, t_max, t_delta, next }
+    }
+}
+
+impl<L> Iterator for Traversal<L> where L: Linear {
+    type Item = Crossing;
+
+    fn next(&mut self) -> Option<Crossing> {
+        let current = self.next.take()?;
+
+        let axis = self.t_max.imin();
+        let lambda = self.t_max[axis];
+        if lambda.is_finite() && self.linear.parameter_on(lambda) {
+            self.cell[axis] += self.step[axis];
+            self.t_max[axis] += self.t_delta[axis];
+
+            let mut normal = V3::zeros();
+            normal[axis] = -self.step[axis];
+            self.next = Some(Crossing { cell: self.cell, normal, lambda });
+        }
+
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(segment: Segment) -> Vec<[i32; 3]> {
+        Traversal::new(segment)
+            .map(|crossing| crossing.cell.into())
+            .collect()
+    }
+
+    #[test]
+    fn axis_aligned() {
+        let crossings: Vec<Crossing> = Traversal::new(
+            Segment::new(P3::new(0.5, 0.5, 0.5), V3::new(3., 0., 0.))
+        ).collect();
+
+        let visited: Vec<[i32; 3]> = crossings.iter().map(|c| c.cell.into()).collect();
+        assert_eq!(visited, vec![[0, 0, 0], [1, 0, 0], [2, 0, 0], [3, 0, 0]]);
+        assert_eq!(crossings[1].normal, V3::new(-1, 0, 0));
+        assert!((crossings[1].lambda - 0.5 / 3.).abs() < 0.0001);
+        assert!((crossings[3].lambda - 2.5 / 3.).abs() < 0.0001);
+
+        let down = Traversal::new(
+            Segment::new(P3::new(0.5, 0.5, 0.5), V3::new(0., 0., -2.))
+        ).collect::<Vec<_>>();
+        assert_eq!(down.len(), 3);
+        assert_eq!(down[2].cell, V3::new(0, 0, -2));
+        assert_eq!(down[2].normal, V3::new(0, 0, 1));
+
+        // ending exactly on a boundary enters the cell beyond
+        assert_eq!(cells(Segment::new(P3::new(0.5, 0.5, 0.5), V3::new(0., 0.5, 0.))).len(), 2);
+        assert_eq!(cells(Segment::new(P3::new(0.5, 0.5, 0.5), V3::new(0., 0.4, 0.))).len(), 1);
+    }
+
+    #[test]
+    fn diagonal() {
+        // every cell is adjacent to the last, and the line leaves each
+        // through the face the next is entered by
+        let segment = Segment::new(P3::new(0.2, 0.7, -0.4), V3::new(4.3, -2.9, 1.7));
+        let crossings: Vec<Crossing> = Traversal::new(segment).collect();
+
+        for pair in crossings.windows(2) {
+            let diff = pair[1].cell - pair[0].cell;
+            assert_eq!(diff.abs().iter().sum::<i32>(), 1);
+            assert_eq!(pair[1].normal, -diff);
+            assert!(pair[1].lambda >= pair[0].lambda);
+
+            let entry = segment.at(pair[1].lambda);
+            let cell = pair[1].cell.map(|x| x as f32);
+            for i in 0 .. 3 {
+                assert!(entry[i] >= cell[i] - 0.0001 && entry[i] <= cell[i] + 1.0001);
+            }
+        }
+
+        let first = crossings.first().unwrap().cell;
+        let last = crossings.last().unwrap().cell;
+        assert_eq!(first, V3::new(0, 0, -1));
+        assert_eq!(last, segment.destination().coords.map(|x| x.floor() as i32));
+        assert_eq!(crossings.len() as i32, 1 + (last - first).abs().iter().sum::<i32>());
+    }
+
+    #[test]
+    fn through_a_corner() {
+        let cells = cells(Segment::new(P3::new(0.5, 0.5, 0.5), V3::new(1., 1., 1.)));
+        assert_eq!(cells.first(), Some(&[0, 0, 0]));
+        assert_eq!(cells.last(), Some(&[1, 1, 1]));
+        assert_eq!(cells.len(), 4);
+    }
+
+    #[test]
+    fn across_chunk_boundaries() {
+        // from inside one 16-block chunk into its negative neighbours
+        let segment = Segment::new(P3::new(16.5, 1.5, 17.3), V3::new(-2., -2.4, -2.));
+        let cells = cells(segment);
+
+        assert_eq!(cells.first(), Some(&[16, 1, 17]));
+        assert_eq!(cells.last(), Some(&[14, -1, 15]));
+        assert_eq!(cells.len(), 7);
+
+        let mut chunks: Vec<[i32; 3]> = cells.iter()
+            .map(|cell| [cell[0] >> 4, cell[1] >> 4, cell[2] >> 4])
+            .collect();
+        chunks.dedup();
+        assert_eq!(chunks, vec![[1, 0, 1], [0, 0, 1], [0, -1, 1], [0, -1, 0]]);
+    }
+}
@@ -2,13 +2,14 @@
 use {
     crate::{
         gl::{self, types::*},
-        block::{self, Block},
+        block::{self, Block, Model, Registry, model::UNITS},
+        light,
         math::*,
     },
     std::{
         mem,
         ptr::null as nullptr,
-        rc::Rc,
+        sync::Arc,
     },
     rgb,
 };
@@ -39,7 +40,7 @@ impl Drop for VAO {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Direction {
     ZOut = 0,
     YOut = 1,
@@ -49,20 +50,281 @@ pub enum Direction {
     XIn  = 5,
 }
 
+impl Direction {
+    pub const ALL: [Direction; 6] = [
+        Direction::ZOut, Direction::YOut, Direction::XOut,
+        Direction::ZIn,  Direction::YIn,  Direction::XIn,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<Direction> {
+        Direction::ALL.get(value as usize).copied()
+    }
+
+    /// The unit vector a face points along
+    pub fn normal(self) -> V3i32 {
+        let mut normal = V3::zeros();
+        normal[self.axis()] = if (self as usize) < 3 { 1 } else { -1 };
+        normal
+    }
+
+    pub fn from_normal(normal: V3i32) -> Option<Direction> {
+        Direction::ALL.iter().copied().find(|dir| dir.normal() == normal)
+    }
+
+    /// The direction nearest to a vector, if it isn't zero
+    pub fn nearest(v: V3) -> Option<Direction> {
+        if v == V3::zeros() {
+            return None;
+        }
+        let axis = v.iamax();
+        let mut normal = V3::zeros();
+        normal[axis] = if v[axis] > 0. { 1 } else { -1 };
+        Direction::from_normal(normal)
+    }
+
+    /// The axis a face points along, as an index into a vector
+    pub fn axis(self) -> usize {
+        2 - (self as usize % 3)
+    }
+
+    /// The axes along which a face's extent runs, as indices into a vector
+    pub fn tangent_axes(self) -> (usize, usize) {
+        match self.axis() {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        }
+    }
+}
+
+/// Collects the quads produced by a `Mesher`
 pub trait MeshBuilder {
-    type Mesh: Mesh + 'static;
+    /// Adds a quad covering `extent` faces, along the face's tangent axes
+    ///
+    /// `rotation` turns the texture on each tile of the quad.
+    ///
+    /// `occlusion` packs a 2-bit ambient occlusion level for each corner of
+    /// the quad, from 0 (darkest) to 3 (unoccluded). The corner at the low
+    /// ends of both tangent axes comes first, in the lowest bits, followed by
+    /// the high end of the first axis, then the high end of the second, then
+    /// both high ends.
+    ///
+    /// `light` holds the block and sky light levels reaching the face.
+    ///
+    /// `shape` trims the quad to part of a face, for blocks which aren't
+    /// cubes.
     fn add_quad(&mut self,
         pos: V3u8,
         dir: Direction,
+        extent: V2u8,
+        color: RGB,
+        tcoords: V2u8,
+        rotation: Rotation,
+        occlusion: u8,
+        light: V2u8,
+        pass: Pass,
+        shape: Shape,
+    );
+
+    /// Adds one side of a quad standing diagonally across the block at `pos`
+    ///
+    /// Diagonals 0 and 1 are the front and back of the quad running from
+    /// the block's -X-Y edge to its +X+Y edge, and 2 and 3 those of the one
+    /// from its -X+Y edge to its +X-Y edge.
+    fn add_diagonal(&mut self,
+        pos: V3u8,
+        diagonal: u8,
         color: RGB,
         tcoords: V2u8,
-        rotate: u8,
+        light: V2u8,
+        pass: Pass,
     );
-    fn bake(&mut self) -> Self::Mesh;
+}
+
+/// The part of a face a quad covers, in sixteenths of a block
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Shape {
+    /// How far in from the low ends of the tangent axes the quad's first
+    /// tile starts, then where its last tile ends
+    pub bounds: V4u8,
+    /// How far the quad is sunk into its block from the face
+    pub depth:  u8,
+}
+
+impl Shape {
+    pub fn full() -> Shape {
+        Shape { bounds: V4::new(0, 0, UNITS, UNITS), depth: 0 }
+    }
+}
+
+/// How the texture on a face is turned
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rotation {
+    /// Quarter turns, each taking the top of the texture from the face's
+    /// second tangent axis round to minus its first
+    Fixed(u8),
+    /// A pseudo-random orientation derived from the block position, to break
+    /// up repetition
+    Random,
+}
+
+impl Rotation {
+    /// As understood by the quad shader
+    fn code(self) -> u8 {
+        match self {
+            Rotation::Fixed(turns) => turns & 3,
+            Rotation::Random       => 4,
+        }
+    }
+}
+
+/// Which of a block's own faces shows in a world direction, and how its
+/// texture must be turned to stay upright with the block
+fn face_of(facing: Direction, dir: Direction, def: &block::BlockDef) -> (Direction, Rotation) {
+    let local = Direction::ALL.iter().copied()
+        .find(|local| def.orient.turn(facing, local.normal()) == dir.normal())
+        .unwrap();
+
+    if def.rotate[local as usize] {
+        return (local, Rotation::Random);
+    }
+
+    // the top of a texture lies along its face's second tangent axis
+    let tangent = |dir: Direction, i: usize| {
+        let axes = dir.tangent_axes();
+        let mut axis = V3::zeros();
+        axis[if i == 0 { axes.0 } else { axes.1 }] = 1;
+        axis
+    };
+    let up = def.orient.turn(facing, tangent(local, 1));
+    let (u, v) = (tangent(dir, 0), tangent(dir, 1));
+
+    let turns = if up == v { 0 } else if up == -u { 1 } else if up == -v { 2 } else { 3 };
+    (local, Rotation::Fixed(turns))
+}
+
+/// Block and sky light levels out of packed light
+fn unpack(packed: u8) -> V2u8 {
+    V2::new(light::Channel::Block.of(packed), light::Channel::Sky.of(packed))
+}
+
+/// The atlas tile and texture rotation of one face of a block
+fn appearance(registry: &Registry, block: Block, dir: Direction) -> (RGB, V2u8, Rotation) {
+    let def = registry.get(block);
+    let (local, rotation) = face_of(block.facing(), dir, def);
+    let variant = block.variant() % def.variants;
+    let tile = def.tiles[local as usize] + V2::new(variant, 0);
+    (def.tint, tile, rotation)
+}
+
+/// Which of a chunk's two meshes a face goes into
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pass {
+    /// Opaque and cutout faces, drawn first in any order
+    Opaque,
+    /// Blended faces, drawn after everything opaque, farthest first
+    Translucent,
+}
+
+impl Pass {
+    fn of(registry: &Registry, block: Block) -> Pass {
+        match registry.get(block).transparency {
+            block::Transparency::Blended => Pass::Translucent,
+            _                            => Pass::Opaque,
+        }
+    }
+}
+
+/// Whether the face of `block` toward `beyond` can be seen
+///
+/// Opaque cubes hide the faces against them, while other blocks hide only
+/// those of their own kind, so that a wall of glass shows no faces inside it.
+/// Empty space has no faces and hides none. Only cubes have faces here; other
+/// models are meshed by `add_models`.
+pub fn face_shows(registry: &Registry, block: Block, beyond: Block) -> bool {
+    if block.is_empty() || !registry.get(block).model.is_cube() {
+        false
+    }
+    else if beyond.is_empty() {
+        true
+    }
+    else {
+        !registry.get(beyond).is_opaque_cube() && !block.same_kind(beyond)
+    }
+}
+
+/// Adds the faces of the chunk's blocks which aren't cubes
+///
+/// Each block's model lies within its own space. Faces of its boxes on the
+/// edge of that space are lit by the block beyond, and left out against an
+/// opaque cube, while those inside it, and crosses, are lit by the block
+/// itself.
+fn add_models(registry: &Registry, input: &block::Slice, light: &light::Slice, builder: &mut impl MeshBuilder) {
+    for xyz in SpaceIter::new(V3::zeros(), input.dims() - V3::repeat(2)) {
+        let at = xyz + V3::repeat(1);
+        let block = input[at];
+        let def = registry.get(block);
+        if block.is_empty() || def.model.is_cube() {
+            continue;
+        }
+
+        let pos = xyz.map(|x| x as u8);
+        let pass = Pass::of(registry, block);
+        let own_light = unpack(light[at]);
+
+        if def.model == Model::Cross {
+            let (tint, tile, _) = appearance(registry, block, Direction::YOut);
+            for diagonal in 0 .. 4 {
+                builder.add_diagonal(pos, diagonal, tint, tile, own_light, pass);
+            }
+            continue;
+        }
+
+        for model_box in def.model.boxes(def.orient, block.facing()) {
+            for &dir in &Direction::ALL {
+                let (u_axis, v_axis) = dir.tangent_axes();
+                let depth = if (dir as usize) < 3 {
+                    UNITS - model_box.maxs[dir.axis()]
+                }
+                else {
+                    model_box.mins[dir.axis()]
+                };
+
+                let levels = if depth == 0 {
+                    let beyond = (at.map(|x| x as i32) + dir.normal()).map(|x| x as usize);
+                    if registry.get(input[beyond]).is_opaque_cube() {
+                        continue;
+                    }
+                    unpack(light[beyond])
+                }
+                else {
+                    own_light
+                };
+
+                let bounds = V4::new(
+                    model_box.mins[u_axis], model_box.mins[v_axis],
+                    model_box.maxs[u_axis], model_box.maxs[v_axis],
+                );
+                let (tint, tile, rotation) = appearance(registry, block, dir);
+                builder.add_quad(
+                    pos, dir, V2::repeat(1), tint, tile, rotation, 0xff, levels, pass,
+                    Shape { bounds, depth },
+                );
+            }
+        }
+    }
 }
 
 pub trait Mesh {
-    fn draw(&self, selected: Option<V3i8>);
+    /// Draws the faces belonging to one pass
+    fn draw(&self, pass: Pass, selected: Option<V3i8>);
+}
+
+/// A mesh with nothing to draw
+pub struct EmptyMesh;
+
+impl Mesh for EmptyMesh {
+    fn draw(&self, _: Pass, _: Option<V3i8>) { }
 }
 
 #[repr(transparent)]
@@ -281,42 +543,26 @@ pub struct Vertex(V4u8);
 
 
 /// A mesh using instanced rendering to draw quads directly
+///
+/// Both passes share a buffer, with the opaque quads first.
 pub struct InstancedQuadMesh {
-    vao:     VAO,
-    n_quads: u32,
+    vao:           VAO,
+    n_opaque:      u32,
+    n_translucent: u32,
 }
 
-impl Mesh for InstancedQuadMesh {
-    fn draw(&self, selected: Option<V3i8>) {
-        self.vao.bind();
-        unsafe {
-            if let Some(selected) = selected {
-                gl::Uniform3i(1, selected.x as i32, selected.y as i32, selected.z as i32);
-            }
-            else {
-                gl::Uniform3i(1, 127, 127, 127);
-            }
-            gl::DrawArraysInstanced(gl::TRIANGLE_FAN, 0, 4, self.n_quads as i32);
-        }
-    }
-}
-
-#[repr(C, packed)]
-struct Quad {
-    pos_dir: V4u8,
-    color:   V4u8,
-    tcoords: V2u8,
-    rot_:    V2u8,
-}
-
-pub struct InstancedQuadMeshBuilder {
-    quads: Vec<Quad>,
-}
-
-impl InstancedQuadMeshBuilder {
-    pub fn new() -> InstancedQuadMeshBuilder {
-        InstancedQuadMeshBuilder {
-            quads: Vec::new(),
+impl InstancedQuadMesh {
+    /// Uploads quads to a new vertex buffer; must be called on the GL thread
+    pub fn upload(quads: &Quads) -> InstancedQuadMesh {
+        let all: Vec<Quad> = quads.opaque.iter()
+            .chain(quads.translucent.iter())
+            .copied()
+            .collect();
+        let vao = Self::prepare_arrays(&all);
+        InstancedQuadMesh {
+            vao,
+            n_opaque:      quads.opaque.len() as u32,
+            n_translucent: quads.translucent.len() as u32,
         }
     }
 
@@ -349,6 +595,22 @@ impl InstancedQuadMeshBuilder {
             gl::VertexArrayAttribIFormat(vao.name(), 3, 2, gl::UNSIGNED_BYTE, 10);
             gl::VertexArrayAttribBinding(vao.name(), 3, 0);
 
+            gl::EnableVertexArrayAttrib(vao.name(), 4);
+            gl::VertexArrayAttribIFormat(vao.name(), 4, 2, gl::UNSIGNED_BYTE, 12);
+            gl::VertexArrayAttribBinding(vao.name(), 4, 0);
+
+            gl::EnableVertexArrayAttrib(vao.name(), 5);
+            gl::VertexArrayAttribIFormat(vao.name(), 5, 2, gl::UNSIGNED_BYTE, 14);
+            gl::VertexArrayAttribBinding(vao.name(), 5, 0);
+
+            gl::EnableVertexArrayAttrib(vao.name(), 6);
+            gl::VertexArrayAttribIFormat(vao.name(), 6, 4, gl::UNSIGNED_BYTE, 16);
+            gl::VertexArrayAttribBinding(vao.name(), 6, 0);
+
+            gl::EnableVertexArrayAttrib(vao.name(), 7);
+            gl::VertexArrayAttribIFormat(vao.name(), 7, 1, gl::UNSIGNED_BYTE, 20);
+            gl::VertexArrayAttribBinding(vao.name(), 7, 0);
+
             gl::VertexArrayVertexBuffer(
                 vao.name(), 0,
                 buf, 0, mem::size_of::<Quad>() as i32
@@ -361,148 +623,766 @@ impl InstancedQuadMeshBuilder {
     }
 }
 
-impl MeshBuilder for InstancedQuadMeshBuilder {
-    type Mesh = InstancedQuadMesh;
+impl Mesh for InstancedQuadMesh {
+    fn draw(&self, pass: Pass, selected: Option<V3i8>) {
+        let (first, count) = match pass {
+            Pass::Opaque      => (0, self.n_opaque),
+            Pass::Translucent => (self.n_opaque, self.n_translucent),
+        };
+        if count == 0 {
+            return;
+        }
+
+        self.vao.bind();
+        unsafe {
+            if let Some(selected) = selected {
+                gl::Uniform3i(1, selected.x as i32, selected.y as i32, selected.z as i32);
+            }
+            else {
+                gl::Uniform3i(1, 127, 127, 127);
+            }
+            gl::DrawArraysInstancedBaseInstance(gl::TRIANGLE_FAN, 0, 4, count as i32, first);
+        }
+    }
+}
+
+/// One quad instance, laid out as the vertex shader expects
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Quad {
+    pos_dir: V4u8,
+    color:   V4u8,
+    tcoords: V2u8,
+    rot_ao:  V2u8,
+    extent:  V2u8,
+    light:   V2u8,
+    bounds:  V4u8,
+    depth:   u8,
+}
 
+/// The code of the first diagonal quad, following the six face directions
+const FIRST_DIAGONAL: u8 = 6;
+
+/// Quad instances built on the CPU, ready to be sent to the GL thread and
+/// uploaded as an `InstancedQuadMesh`
+pub struct Quads {
+    opaque:      Vec<Quad>,
+    translucent: Vec<Quad>,
+}
+
+impl Quads {
+    pub fn new() -> Quads {
+        Quads {
+            opaque:      Vec::new(),
+            translucent: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.opaque.len() + self.translucent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&mut self, quad: Quad, pass: Pass) {
+        match pass {
+            Pass::Opaque      => self.opaque.push(quad),
+            Pass::Translucent => self.translucent.push(quad),
+        }
+    }
+}
+
+impl MeshBuilder for Quads {
     fn add_quad(&mut self,
         pos:     V3u8,
         dir:     Direction,
+        extent:  V2u8,
         color:   RGB,
         tcoords: V2u8,
-        rot:     u8,
+        rot:     Rotation,
+        ao:      u8,
+        light:   V2u8,
+        pass:    Pass,
+        shape:   Shape,
     ) {
         let color: [u8; 3] = color.into();
-        let quad = Quad {
+        self.push(Quad {
             pos_dir: pos.push(dir as u8),
             color:   V3u8::from(color).push(255),
             tcoords,
-            rot_: V2u8::new(rot, 0),
-        };
-        self.quads.push(quad);
+            rot_ao:  V2u8::new(rot.code(), ao),
+            extent,
+            light,
+            bounds:  shape.bounds,
+            depth:   shape.depth,
+        }, pass);
     }
 
-    fn bake(&mut self) -> InstancedQuadMesh {
-        let n_quads = self.quads.len() as u32;
-        let vao = Self::prepare_arrays(&self.quads);
-        self.quads.clear();
-        InstancedQuadMesh { vao, n_quads }
+    fn add_diagonal(&mut self,
+        pos:      V3u8,
+        diagonal: u8,
+        color:    RGB,
+        tcoords:  V2u8,
+        light:    V2u8,
+        pass:     Pass,
+    ) {
+        let color: [u8; 3] = color.into();
+        let shape = Shape::full();
+        self.push(Quad {
+            pos_dir: pos.push(FIRST_DIAGONAL + diagonal),
+            color:   V3u8::from(color).push(255),
+            tcoords,
+            rot_ao:  V2u8::new(Rotation::Fixed(0).code(), 0xff),
+            extent:  V2::repeat(1),
+            light,
+            bounds:  shape.bounds,
+            depth:   shape.depth,
+        }, pass);
     }
 }
 
-pub trait Mesher {
-    fn make_mesh(&self, input: block::Slice, builder: &mut impl MeshBuilder)
-        -> Rc<dyn Mesh>;
+/// Turns a chunk's blocks into a mesh
+///
+/// The input holds the chunk surrounded by a one-block border on every side,
+/// taken from its neighbours, along with the packed light of each of those
+/// blocks. Each face is lit by the block it looks into.
+///
+/// The mesh covers the faces between the chunk's blocks and those of its +X,
+/// +Y and +Z neighbours, while faces on its -X, -Y and -Z boundaries belong
+/// to the neighbours' meshes.
+///
+/// Meshing touches no GL state, so it may run on any thread.
+pub trait Mesher: Send + Sync + 'static {
+    fn make_mesh(&self, input: block::Slice, light: light::Slice, builder: &mut impl MeshBuilder);
 }
 
+/// Makes one quad per visible face, as the reference the tests hold
+/// `Greedy` to
+#[cfg(test)]
 pub struct Simple {
+    registry: Arc<Registry>,
 }
 
+#[cfg(test)]
 impl Simple {
-    pub fn new() -> Simple {
-        Simple { }
+    pub fn new(registry: Arc<Registry>) -> Simple {
+        Simple { registry }
     }
 }
 
-trait BlockProps : Copy {
-    fn color(self) -> RGB;
-    fn tcoords(self, dir: Direction) -> V2u8;
-    fn rotate(self, dir: Direction) -> bool;
+/// Computes packed ambient occlusion levels for the corners of a face
+///
+/// `air` is the position in the meshing buffer of the block the face looks
+/// into, which isn't an opaque cube. Each corner is darkened by the opaque
+/// cubes beside it and diagonal to it, as in the order described by
+/// `MeshBuilder::add_quad`.
+fn occlusion(registry: &Registry, input: &block::Slice, air: V3usize, dir: Direction) -> u8 {
+    let (u_axis, v_axis) = dir.tangent_axes();
+    let solid = |du: usize, dv: usize| {
+        let mut at = air;
+        at[u_axis] = at[u_axis] + du - 1;
+        at[v_axis] = at[v_axis] + dv - 1;
+        registry.get(input[at]).is_opaque_cube()
+    };
+
+    (0 .. 4).fold(0, |packed, corner| {
+        let du = (corner & 1) * 2;
+        let dv = (corner >> 1) * 2;
+
+        let side_u = solid(du, 1);
+        let side_v = solid(1, dv);
+        let level = if side_u && side_v {
+            0
+        }
+        else {
+            3 - (side_u as u8 + side_v as u8 + solid(du, dv) as u8)
+        };
+
+        packed | (level << (2 * corner))
+    })
 }
 
-impl BlockProps for Block {
-    fn color(self) -> RGB {
-        use Block::*;
-        match self {
-            //Stone => RGB::new(180, 180, 180),
-            //Grass => RGB::new( 32, 180,  32),
-            //Soil  => RGB::new( 96,  48,  32),
-            Empty => unreachable!(),
-            _     => RGB::new(255, 255, 255),
+#[cfg(test)]
+impl Mesher for Simple {
+    fn make_mesh(&self, input: block::Slice, light: light::Slice, builder: &mut impl MeshBuilder) {
+        use Direction::*;
+
+        // careful with the indices here
+        for xyz in SpaceIter::new(V3::zeros(), input.dims() - V3::repeat(2)) {
+            let pos = xyz.map(|x| x as u8);
+            let at = xyz + V3::repeat(1);
+
+            let block = input[at];
+
+            let mut add_quad = |pos: V3u8, dir: Direction, block: Block, air: V3usize| {
+                let (tint, tile, rotation) = appearance(&self.registry, block, dir);
+                let ao = occlusion(&self.registry, &input, air, dir);
+                let levels = unpack(light[air]);
+                let pass = Pass::of(&self.registry, block);
+                builder.add_quad(pos, dir, V2::repeat(1), tint, tile, rotation, ao, levels, pass, Shape::full());
+            };
+
+            // each block meets its +X, +Y and +Z neighbours, and either side
+            // of the boundary may show a face
+            for &(out_dir, in_dir) in &[(ZOut, ZIn), (YOut, YIn), (XOut, XIn)] {
+                let step = out_dir.normal().map(|x| x as usize);
+                let beyond = input[at + step];
+                if face_shows(&self.registry, block, beyond) {
+                    add_quad(pos, out_dir, block, at + step);
+                }
+                if face_shows(&self.registry, beyond, block) {
+                    add_quad(pos + step.map(|x| x as u8), in_dir, beyond, at);
+                }
+            }
         }
+
+        add_models(&self.registry, &input, &light, builder);
+    }
+}
+
+/// A mesher which merges runs of matching faces into larger quads
+///
+/// Each layer of faces perpendicular to an axis is gathered into a 2D mask,
+/// which is then covered with rectangles greedily: each grows as wide as it
+/// can along the first tangent axis, then as tall as whole rows allow.
+pub struct Greedy {
+    registry: Arc<Registry>,
+}
+
+impl Greedy {
+    pub fn new(registry: Arc<Registry>) -> Greedy {
+        Greedy { registry }
     }
+}
+
+/// Everything which must match for two faces to be merged
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FaceKey {
+    block:     Block,
+    dir:       Direction,
+    occlusion: u8,
+    light:     u8,
+}
+
+impl Mesher for Greedy {
+    fn make_mesh(&self, input: block::Slice, light: light::Slice, builder: &mut impl MeshBuilder) {
+        use Direction::*;
+
+        let dims = input.dims() - V3::repeat(2);
+        let mut mask: Vec<Option<FaceKey>> = Vec::new();
+
+        for &(axis, out_dir, in_dir) in &[(2, ZOut, ZIn), (1, YOut, YIn), (0, XOut, XIn)] {
+            let (u_axis, v_axis) = out_dir.tangent_axes();
+            let (u_dim, v_dim) = (dims[u_axis], dims[v_axis]);
+
+            // faces either way across a layer may both show, so each
+            // direction is gathered into a mask of its own
+            for &dir in &[out_dir, in_dir] {
+                for layer in 0 .. dims[axis] {
+                    mask.clear();
+                    for v in 0 .. v_dim {
+                        for u in 0 .. u_dim {
+                            let mut at = V3::repeat(1);
+                            at[axis] += layer;
+                            at[u_axis] += u;
+                            at[v_axis] += v;
 
-    fn tcoords(self, dir: Direction) -> V2u8 {
-        use {Block::*, Direction::*};
-        let (x, y) = match self {
-            Stone => (0, 0),
+                            let mut next = at;
+                            next[axis] += 1;
 
-            Grass => match dir {
-                ZOut => (1, 0),
-                ZIn  => (2, 0),
-                _    => (3, 0),
-            },
+                            // the block the face belongs to, and the one it looks into
+                            let (from, into) = if dir == out_dir { (at, next) } else { (next, at) };
+                            let face = if face_shows(&self.registry, input[from], input[into]) {
+                                let occlusion = occlusion(&self.registry, &input, into, dir);
+                                Some(FaceKey { block: input[from], dir, occlusion, light: light[into] })
+                            }
+                            else {
+                                None
+                            };
 
-            Soil => (2, 0),
+                            mask.push(face);
+                        }
+                    }
 
-            TreeTrunk => match dir {
-                ZIn | ZOut => (5, 0),
-                _          => (4, 0),
+                    for v in 0 .. v_dim {
+                        let mut u = 0;
+                        while u < u_dim {
+                            let key = match mask[v * u_dim + u] {
+                                Some(key) => key,
+                                None      => { u += 1; continue; }
+                            };
+
+                            let width = (u .. u_dim)
+                                .take_while(|&u| mask[v * u_dim + u] == Some(key))
+                                .count();
+
+                            let height = 1 + (v + 1 .. v_dim)
+                                .take_while(|&v| {
+                                    (u .. u + width).all(|u| mask[v * u_dim + u] == Some(key))
+                                })
+                                .count();
+
+                            for v in v .. v + height {
+                                for u in u .. u + width {
+                                    mask[v * u_dim + u] = None;
+                                }
+                            }
+
+                            let mut pos = V3::zeros();
+                            pos[axis] = if key.dir == out_dir { layer } else { layer + 1 };
+                            pos[u_axis] = u;
+                            pos[v_axis] = v;
+
+                            let (tint, tile, rotation) = appearance(&self.registry, key.block, key.dir);
+                            builder.add_quad(
+                                pos.map(|x| x as u8),
+                                key.dir,
+                                V2::new(width as u8, height as u8),
+                                tint,
+                                tile,
+                                rotation,
+                                key.occlusion,
+                                unpack(key.light),
+                                Pass::of(&self.registry, key.block),
+                                Shape::full(),
+                            );
+
+                            u += width;
+                        }
+                    }
+                }
             }
+        }
+
+        add_models(&self.registry, &input, &light, builder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            array3d,
+            chunk::{self, Chunk, Coords},
+            chunk_maker,
+            block::stock::{self, *},
+            chunk_source::ChunkMaker,
+        },
+        std::collections::HashSet,
+    };
+
+    const DIM: usize = chunk::DIM as usize;
+
+    fn registry() -> Arc<Registry> {
+        Arc::new(stock::registry())
+    }
 
-            Empty => unreachable!(),
+    fn maker() -> chunk_maker::Test {
+        chunk_maker::Test::new(12345, &registry()).unwrap()
+    }
+
+    #[derive(Default, Clone)]
+    struct Dims;
+
+    impl array3d::StaticDims for Dims {
+        const X: usize = DIM + 2;
+        const Y: usize = DIM + 2;
+        const Z: usize = DIM + 2;
+    }
+
+    type Buffer = array3d::ArrayOwned<Block, Dims>;
+    type LightBuffer = array3d::ArrayOwned<u8, Dims>;
+
+    /// Records quads rather than uploading them
+    #[derive(Default)]
+    struct Recorder {
+        quads:     Vec<(V3u8, Direction, V2u8, V2u8, u8, V2u8, Pass, Shape)>,
+        diagonals: Vec<(V3u8, u8, V2u8, Pass)>,
+    }
+
+    impl MeshBuilder for Recorder {
+        fn add_quad(&mut self,
+            pos:     V3u8,
+            dir:     Direction,
+            extent:  V2u8,
+            _:       RGB,
+            tcoords: V2u8,
+            _:       Rotation,
+            ao:      u8,
+            light:   V2u8,
+            pass:    Pass,
+            shape:   Shape,
+        ) {
+            self.quads.push((pos, dir, extent, tcoords, ao, light, pass, shape));
+        }
+
+        fn add_diagonal(&mut self,
+            pos:      V3u8,
+            diagonal: u8,
+            _:        RGB,
+            _:        V2u8,
+            light:    V2u8,
+            pass:     Pass,
+        ) {
+            self.diagonals.push((pos, diagonal, light, pass));
+        }
+    }
+
+    impl Recorder {
+        /// Every unit face covered by the recorded quads
+        fn unit_faces(&self) -> HashSet<([u8; 3], u8, [u8; 2], u8, [u8; 2], [u8; 4], u8)> {
+            let mut faces = HashSet::new();
+            for &(pos, dir, extent, tcoords, ao, light, _, shape) in &self.quads {
+                let (u_axis, v_axis) = dir.tangent_axes();
+                for v in 0 .. extent.y {
+                    for u in 0 .. extent.x {
+                        let mut at = pos;
+                        at[u_axis] += u;
+                        at[v_axis] += v;
+                        let key = (at.into(), dir as u8, tcoords.into(), ao, light.into(), shape.bounds.into(), shape.depth);
+                        let new = faces.insert(key);
+                        assert!(new, "overlapping quads");
+                    }
+                }
+            }
+            faces
+        }
+    }
+
+    fn terrain_buffer(maker: &chunk_maker::Test, coords: Coords) -> Buffer {
+        let mut made = std::collections::HashMap::<Coords, Chunk>::new();
+        let mut chunk_at = |coords: Coords| -> Chunk {
+            if !made.contains_key(&coords) {
+                made.extend(maker.make(coords));
+            }
+            made[&coords].clone()
+        };
+
+        let span = |step: i32| match step {
+            -1 => (DIM - 1, 0,       1),
+             0 => (0,       1,       DIM),
+             _ => (0,       DIM + 1, 1),
         };
-        V2::new(x, y)
+
+        let mut buffer = Buffer::new_filled(Block::EMPTY);
+        for step in SpaceIter::new(V3::repeat(-1), V3::repeat(2)) {
+            let spans = step.map(span);
+            chunk_at(coords + step).copy_into(
+                spans.map(|(src, _, _)| src),
+                &mut buffer.slice_mut(
+                    spans.map(|(_, dst, _)| dst),
+                    spans.map(|(_, _, dims)| dims)
+                )
+            );
+        }
+        buffer
     }
 
-    fn rotate(self, dir: Direction) -> bool {
-        use {Block::*, Direction::*};
-        match self {
-            Stone | Soil
-                => true,
+    fn mesh(mesher: &impl Mesher, buffer: &Buffer) -> Recorder {
+        mesh_lit(mesher, buffer, &LightBuffer::new_filled(0))
+    }
 
-            Grass if dir == ZOut
-                => true,
+    fn mesh_lit(mesher: &impl Mesher, buffer: &Buffer, light: &LightBuffer) -> Recorder {
+        let mut recorder = Recorder::default();
+        mesher.make_mesh(buffer.whole_slice(), light.whole_slice(), &mut recorder);
+        recorder
+    }
 
-            TreeTrunk if dir == ZOut || dir == ZIn
-                => true,
+    #[test]
+    fn greedy_covers_same_faces_with_fewer_quads() {
+        let maker = maker();
+        let (simple, greedy) = (Simple::new(registry()), Greedy::new(registry()));
+        let mut total_simple = 0;
+        let mut total_greedy = 0;
 
-            Empty => unreachable!(),
-            _     => false
+        for xyz in SpaceIter::new(V3::new(1, 1, -2), V3::new(5, 5, 1)) {
+            let buffer = terrain_buffer(&maker, Coords::new(xyz.into()));
+            let simple = mesh(&simple, &buffer);
+            let greedy = mesh(&greedy, &buffer);
+
+            assert!(greedy.unit_faces() == simple.unit_faces());
+            assert!(greedy.quads.len() <= simple.quads.len());
+            total_simple += simple.quads.len();
+            total_greedy += greedy.quads.len();
         }
+
+        assert!(total_simple > 0);
+        // differing ambient occlusion keeps some neighbouring faces apart
+        assert!(
+            total_greedy * 3 < total_simple * 2,
+            "simple: {} quads, greedy: {} quads", total_simple, total_greedy
+        );
     }
-}
 
+    #[test]
+    fn greedy_merges_flat_floor() {
+        let mut buffer = Buffer::new_filled(Block::EMPTY);
+        for xy in SpaceIter::new(V3::zeros(), V3::new(DIM + 2, DIM + 2, 2)) {
+            buffer[xy] = STONE;
+        }
+
+        let greedy = mesh(&Greedy::new(registry()), &buffer);
+        let simple = mesh(&Simple::new(registry()), &buffer);
+        assert_eq!(simple.quads.len(), DIM * DIM);
+        assert_eq!(greedy.quads.len(), 1);
+        assert!(greedy.quads[0].2 == V2::repeat(DIM as u8));
+        assert_eq!(greedy.quads[0].4, 0xff);
+    }
 
-impl Mesher for Simple {
-    fn make_mesh(&self, input: block::Slice, builder: &mut impl MeshBuilder)
-        -> Rc<dyn Mesh>
-    {
-        use Direction::*;
+    #[test]
+    fn occlusion_beside_a_wall() {
+        // a floor at z = 0 with a wall along x = 4, in chunk coordinates
+        let mut buffer = Buffer::new_filled(Block::EMPTY);
+        for xyz in buffer.indices() {
+            let (x, z) = (xyz.x as i32 - 1, xyz.z as i32 - 1);
+            if z == 0 || (x == 4 && z < 3) {
+                buffer[xyz] = STONE;
+            }
+        }
 
-        // careful with the indices here
-        for xyz in SpaceIter::new(V3::zeros(), input.dims() - V3::new(1,1,1)) {
-            let pos = xyz.map(|x| x as u8);
-            let hash
-                = pos.x.wrapping_mul(251)
-                ^ pos.y.wrapping_mul(199)
-                ^ pos.z.wrapping_mul(151);
-
-            let block = input[xyz];
-
-            let bz = input[xyz + V3::z()];
-            let by = input[xyz + V3::y()];
-            let bx = input[xyz + V3::x()];
-
-            let mut add_quad = |pos: V3u8, dir: Direction, block: Block| {
-                let color   = block.color();
-                let tcoords = block.tcoords(dir);
-                let rotate  = if block.rotate(dir) { hash } else { 0 };
-                builder.add_quad(pos, dir, color, tcoords, rotate);
-            };
+        let simple = mesh(&Simple::new(registry()), &buffer);
+        let floor_ao = |x: u8| simple.quads.iter()
+            .find(|q| q.0 == V3::new(x, 5, 0) && q.1 == Direction::ZOut)
+            .unwrap()
+            .4;
 
-            if block.is_nonempty() {
-                if bz.is_empty() { add_quad(pos, ZOut, block); }
-                if by.is_empty() { add_quad(pos, YOut, block); }
-                if bx.is_empty() { add_quad(pos, XOut, block); }
+        // corners on the high-x side touch the wall, at both y ends
+        let ao = floor_ao(3);
+        let levels: Vec<u8> = (0 .. 4).map(|c| (ao >> (2 * c)) & 3).collect();
+        assert_eq!(levels, vec![3, 1, 3, 1]);
+
+        // two blocks away from the wall nothing is occluded
+        assert_eq!(floor_ao(2), 0xff);
+
+        // inside corners where floor meets wall are darker along the wall
+        let wall_ao = simple.quads.iter()
+            .find(|q| q.0 == V3::new(4, 5, 1) && q.1 == Direction::XIn)
+            .unwrap()
+            .4;
+        let levels: Vec<u8> = (0 .. 4).map(|c| (wall_ao >> (2 * c)) & 3).collect();
+        assert_eq!(levels, vec![1, 1, 3, 3]);
+    }
+
+    #[test]
+    fn faces_take_light_from_the_block_they_face() {
+        // a floor lit from a spot above its middle, fading outward, and by
+        // the sky growing brighter along Y
+        let mut buffer = Buffer::new_filled(Block::EMPTY);
+        let mut light = LightBuffer::new_filled(0);
+        for xyz in buffer.indices() {
+            let from_spot = (xyz.x as i32 - 8).abs() + (xyz.y as i32 - 8).abs() + (xyz.z as i32 - 2).abs();
+            if xyz.z == 1 {
+                buffer[xyz] = STONE;
             }
             else {
-                if bz.is_nonempty() { add_quad(pos + V3::z(), ZIn, bz); }
-                if by.is_nonempty() { add_quad(pos + V3::y(), YIn, by); }
-                if bx.is_nonempty() { add_quad(pos + V3::x(), XIn, bx); }
+                light[xyz] = (14 - from_spot).max(0) as u8 | ((xyz.y as u8 / 2) << 4);
             }
         }
 
-        Rc::new(builder.bake())
+        let registry = registry();
+        let (simple, greedy) = (Simple::new(registry.clone()), Greedy::new(registry.clone()));
+        let simple = mesh_lit(&simple, &buffer, &light);
+        let greedy = mesh_lit(&greedy, &buffer, &light);
+        assert!(greedy.unit_faces() == simple.unit_faces());
+
+        let floor_light = |x: u8, y: u8| simple.quads.iter()
+            .find(|q| q.0 == V3::new(x, y, 0) && q.1 == Direction::ZOut)
+            .unwrap()
+            .5;
+        assert_eq!(floor_light(7, 7), V2::new(14, 4));
+        assert_eq!(floor_light(10, 7), V2::new(11, 4));
+        assert_eq!(floor_light(15, 15), V2::new(0, 8));
+
+        // faces differently lit aren't merged
+        assert!(greedy.quads.len() > 2 * DIM);
     }
-}
 
+    #[test]
+    fn transparent_blocks_hide_only_their_own_kind() {
+        use Direction::*;
+
+        // in chunk coordinates, along X: water, two glass, stone, leaves
+        let mut buffer = Buffer::new_filled(Block::EMPTY);
+        let row = [(1, WATER), (2, GLASS), (3, GLASS), (4, STONE), (5, LEAVES)];
+        for &(x, block) in &row {
+            buffer[V3::new(x + 1, 6, 6)] = block;
+        }
+
+        let registry = registry();
+        let simple = mesh(&Simple::new(registry.clone()), &buffer);
+        let greedy = mesh(&Greedy::new(registry.clone()), &buffer);
+        assert!(greedy.unit_faces() == simple.unit_faces());
+
+        let face = |x: u8, dir| simple.quads.iter()
+            .find(|q| q.0 == V3::new(x, 5, 5) && q.1 == dir)
+            .map(|q| q.6);
+
+        // glass beside glass shows nothing between them
+        assert_eq!(face(2, XOut), None);
+        assert_eq!(face(3, XIn), None);
+
+        // glass beside stone shows the stone, but not the glass against it
+        assert_eq!(face(4, XIn), Some(Pass::Opaque));
+        assert_eq!(face(3, XOut), None);
+
+        // water beside glass shows both, blended
+        assert_eq!(face(1, XOut), Some(Pass::Translucent));
+        assert_eq!(face(2, XIn), Some(Pass::Translucent));
+
+        // cutout leaves are drawn with the opaque faces, and show the stone
+        assert_eq!(face(5, XIn), None);
+        assert_eq!(face(4, XOut), Some(Pass::Opaque));
+        assert_eq!(face(5, ZOut), Some(Pass::Opaque));
+
+        // transparent blocks cast no ambient occlusion
+        let stone_side = simple.quads.iter()
+            .find(|q| q.0 == V3::new(4, 5, 5) && q.1 == XIn)
+            .unwrap();
+        assert_eq!(stone_side.4, 0xff);
+
+        let passes = |pass| simple.quads.iter().filter(|q| q.6 == pass).count();
+        assert_eq!(passes(Pass::Translucent), 6 + 5 + 4);
+    }
+
+    #[test]
+    fn models() {
+        use Direction::*;
+
+        // in chunk coordinates: a slab on stone, stairs facing +X and a
+        // flower, lit more brightly the higher up they are
+        let mut buffer = Buffer::new_filled(Block::EMPTY);
+        let mut light = LightBuffer::new_filled(0);
+        let blocks = [
+            ((2, 5, 4), STONE),
+            ((2, 5, 5), STONE_SLAB),
+            ((6, 5, 5), STONE_STAIRS.with_facing(XOut)),
+            ((9, 5, 5), FLOWER),
+        ];
+        for &((x, y, z), block) in &blocks {
+            buffer[V3::new(x + 1, y + 1, z + 1)] = block;
+        }
+        for xyz in light.indices() {
+            light[xyz] = xyz.z as u8;
+        }
+
+        let registry = registry();
+        let simple = mesh_lit(&Simple::new(registry.clone()), &buffer, &light);
+        let greedy = mesh_lit(&Greedy::new(registry.clone()), &buffer, &light);
+        assert!(greedy.unit_faces() == simple.unit_faces());
+        assert_eq!(greedy.diagonals, simple.diagonals);
+
+        let faces = |x: u8, dir| -> Vec<(Shape, V2u8)> {
+            simple.quads.iter()
+                .filter(|q| q.0 == V3::new(x, 5, 5) && q.1 == dir)
+                .map(|q| (q.7, q.5))
+                .collect()
+        };
+        let shape = |bounds: [u8; 4], depth| Shape { bounds: bounds.into(), depth };
+
+        // the slab's top is sunk halfway and lit from within, while its
+        // sides are cut to its height and lit from beside it
+        assert_eq!(faces(2, ZOut), vec![(shape([0, 0, 16, 16], 8), V2::new(6, 0))]);
+        assert_eq!(faces(2, XOut), vec![(shape([0, 0, 16, 8], 0), V2::new(6, 0))]);
+        assert_eq!(faces(2, ZIn), vec![]);
+
+        // the stone beneath still shows its top, as the slab doesn't cover it
+        let stone_top = simple.quads.iter().find(|q| q.0 == V3::new(2, 5, 4) && q.1 == ZOut);
+        assert_eq!(stone_top.map(|q| q.7), Some(Shape::full()));
+
+        // the stairs' step stands at -X, away from where they face
+        let stair_tops = faces(6, ZOut);
+        assert_eq!(stair_tops.len(), 2);
+        assert!(stair_tops.contains(&(shape([0, 0, 8, 16], 0), V2::new(7, 0))));
+        assert!(stair_tops.contains(&(shape([0, 0, 16, 16], 8), V2::new(6, 0))));
+
+        // the flower is two quads crossing, each with two sides
+        assert_eq!(simple.diagonals.len(), 4);
+        assert!(simple.diagonals.iter().all(|d| d.0 == V3::new(9, 5, 5) && d.2 == V2::new(6, 0)));
+        assert_eq!(faces(9, ZOut), vec![]);
+
+        // models are left to their own chunk's mesh
+        let mut border = Buffer::new_filled(Block::EMPTY);
+        border[V3::new(0, 5, 5)] = STONE_SLAB;
+        border[V3::new(DIM + 1, 5, 5)] = FLOWER;
+        let border = mesh(&Simple::new(registry.clone()), &border);
+        assert!(border.quads.is_empty() && border.diagonals.is_empty());
+    }
+
+    #[test]
+    fn turned_blocks() {
+        use Direction::*;
+        let registry = registry();
+        let face = |block: Block, dir| {
+            let (_, tile, rotation) = appearance(&registry, block, dir);
+            (tile, rotation)
+        };
+        let (end, bark) = (V2::new(5, 0), V2::new(4, 0));
+
+        // upright, with bark upright on every side
+        assert_eq!(face(TREE_TRUNK, ZOut), (end, Rotation::Random));
+        assert_eq!(face(TREE_TRUNK, ZIn), (end, Rotation::Random));
+        for &dir in &[YOut, XOut, YIn, XIn] {
+            assert_eq!(face(TREE_TRUNK, dir), (bark, Rotation::Fixed(0)));
+        }
+
+        // lying along X, with the bark turned to run along it
+        let log = TREE_TRUNK.with_facing(XOut);
+        assert_eq!(face(log, XOut), (end, Rotation::Random));
+        assert_eq!(face(log, XIn), (end, Rotation::Random));
+        assert_eq!(face(log, ZOut), (bark, Rotation::Fixed(3)));
+        assert_eq!(face(log, YOut), (bark, Rotation::Fixed(3)));
+
+        // lying along Y, the bark on the ends of the X axis needs no turn
+        let log = TREE_TRUNK.with_facing(YIn);
+        assert_eq!(face(log, YOut), (end, Rotation::Random));
+        assert_eq!(face(log, XOut).0, bark);
+        assert_eq!(face(log, XOut).1, face(log, XIn).1);
+
+        // upside down
+        let log = TREE_TRUNK.with_facing(ZIn);
+        assert_eq!(face(log, ZOut), (end, Rotation::Random));
+        assert_eq!(face(log, YOut), (bark, Rotation::Fixed(2)));
+
+        // every way a block can face shows each of its own faces once
+        for &facing in &Direction::ALL {
+            let mut locals: Vec<u8> = Direction::ALL.iter()
+                .map(|&dir| face_of(facing, dir, registry.get(STONE)).0 as u8)
+                .collect();
+            locals.sort();
+            assert_eq!(locals, vec![0, 1, 2, 3, 4, 5]);
+            assert_eq!(block::Orient::Facing.turn(facing, V3::z()), facing.normal());
+        }
+    }
+
+    #[test]
+    fn variants() {
+        let registry = Registry::parse("[9]\nname = crop\ntile = 2 1\nvariants = 3").unwrap();
+        let crop = Block::from_id(9);
+        let tile = |variant| appearance(&registry, crop.with_variant(variant), Direction::YOut).1;
+
+        assert_eq!(tile(0), V2::new(2, 1));
+        assert_eq!(tile(2), V2::new(4, 1));
+        // out of range variants wrap
+        assert_eq!(tile(4), V2::new(3, 1));
+    }
+
+    #[test]
+    fn quads_are_built_off_thread() {
+        let maker = maker();
+        let buffer = terrain_buffer(&maker, Coords::new(P3::new(3, 3, 0)));
+        let greedy = Greedy::new(registry());
+        let recorded = mesh(&greedy, &buffer).quads.len();
+        assert!(recorded > 0);
+
+        let quads = std::thread::spawn(move || {
+            let mut quads = Quads::new();
+            greedy.make_mesh(buffer.whole_slice(), LightBuffer::new_filled(0).whole_slice(), &mut quads);
+            quads
+        }).join().unwrap();
+
+        assert_eq!(quads.len(), recorded);
+    }
+}
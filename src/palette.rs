@@ -0,0 +1,225 @@
+
+use {
+    std::ops::Index,
+    crate::{
+        array3d::{self, Dims, StaticDims},
+        math::*,
+    },
+};
+
+const WORD_BITS: u32 = 64;
+
+/// A 3D array storing a palette of distinct values plus a bit-packed palette
+/// index per element
+///
+/// Indices start at 1 bit per element and widen to 2, 4, 8 and finally 16 bits
+/// as new values are written. Entries never straddle a word, and the palette
+/// is not compacted when values are overwritten; rebuild the array from an
+/// iterator to do that. Elements cannot be borrowed mutably in place, so
+/// they are only written through `set`.
+#[derive(Clone)]
+pub struct PaletteArray<T, D> {
+    palette: Vec<T>,
+    bits:    u32,
+    words:   Vec<u64>,
+    dims:    D,
+}
+
+impl<T, D> PaletteArray<T, D> where T: Copy + PartialEq, D: StaticDims {
+    pub fn new_filled(with: T) -> Self {
+        let dims = D::default();
+        let bits = 1;
+        PaletteArray {
+            palette: vec![with],
+            bits,
+            words:   vec![0; Self::word_count(dims.volume(), bits)],
+            dims,
+        }
+    }
+
+    fn word_count(volume: usize, bits: u32) -> usize {
+        let per_word = (WORD_BITS / bits) as usize;
+        (volume + per_word - 1) / per_word
+    }
+
+    pub fn volume(&self) -> usize {
+        self.dims.volume()
+    }
+
+    pub fn palette(&self) -> &[T] {
+        &self.palette
+    }
+
+    /// Approximate bytes allocated for the palette and indices
+    pub fn heap_size(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+            + self.palette.len() * std::mem::size_of::<T>()
+    }
+
+    fn read(&self, flat: usize) -> usize {
+        let per_word = (WORD_BITS / self.bits) as usize;
+        let shift = (flat % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        ((self.words[flat / per_word] >> shift) & mask) as usize
+    }
+
+    fn write(&mut self, flat: usize, index: usize) {
+        let per_word = (WORD_BITS / self.bits) as usize;
+        let shift = (flat % per_word) as u32 * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.words[flat / per_word];
+        *word = (*word & !mask) | ((index as u64) << shift);
+    }
+
+    fn widen(&mut self) {
+        let indices: Vec<usize> = (0 .. self.volume())
+            .map(|flat| self.read(flat))
+            .collect();
+
+        self.bits *= 2;
+        assert!(self.bits <= 16, "palette overflow");
+        self.words = vec![0; Self::word_count(self.volume(), self.bits)];
+
+        for (flat, index) in indices.into_iter().enumerate() {
+            self.write(flat, index);
+        }
+    }
+
+    fn palette_index(&mut self, value: T) -> usize {
+        if let Some(index) = self.palette.iter().position(|v| *v == value) {
+            return index;
+        }
+
+        let index = self.palette.len();
+        if index >> self.bits != 0 {
+            self.widen();
+        }
+
+        self.palette.push(value);
+        index
+    }
+
+    pub fn get(&self, ijk: V3usize) -> &T {
+        &self.palette[self.read(self.dims.flat_index(ijk))]
+    }
+
+    pub fn set(&mut self, ijk: V3usize, value: T) {
+        let index = self.palette_index(value);
+        self.write(self.dims.flat_index(ijk), index);
+    }
+
+    pub fn copy_into(&self, offset: V3usize, dst: &mut array3d::ArraySliceMut<'_, T>) {
+        for (ijk, elem) in dst.indexed_iter_mut() {
+            *elem = *self.get(offset + ijk);
+        }
+    }
+}
+
+impl<T, D> Index<V3usize> for PaletteArray<T, D> where T: Copy + PartialEq, D: StaticDims {
+    type Output = T;
+    fn index(&self, ijk: V3usize) -> &T {
+        self.get(ijk)
+    }
+}
+
+impl<T, D> std::iter::FromIterator<T> for PaletteArray<T, D> where T: Copy + PartialEq, D: StaticDims {
+    fn from_iter<I> (iter: I) -> Self where I: IntoIterator<Item = T> {
+        let mut iter = iter.into_iter();
+        let first = iter.next().expect("empty iterator");
+        let mut array = Self::new_filled(first);
+
+        let mut count = 1;
+        for value in iter {
+            let index = array.palette_index(value);
+            array.write(count, index);
+            count += 1;
+        }
+
+        assert!(count == array.volume());
+        array
+    }
+}
+
+impl<T, D, H> From<&array3d::Array<D, H>> for PaletteArray<T, D>
+    where T: Copy + PartialEq,
+          D: StaticDims,
+          H: array3d::Hold<Element = T>,
+{
+    fn from(array: &array3d::Array<D, H>) -> Self {
+        array.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Dims;
+
+    impl StaticDims for Dims {
+        const X: usize = 5;
+        const Y: usize = 5;
+        const Z: usize = 5;
+    }
+
+    type Packed = PaletteArray<u16, Dims>;
+
+    fn values(packed: &Packed) -> Vec<u16> {
+        SpaceIter::new(V3::zeros(), V3::repeat(5))
+            .map(|ijk| packed[ijk])
+            .collect()
+    }
+
+    #[test]
+    fn widens_as_values_appear() {
+        let mut packed = Packed::new_filled(7);
+        assert_eq!(packed.bits, 1);
+        assert!(values(&packed).iter().all(|x| *x == 7));
+
+        packed.set(V3::new(0, 0, 0), 8);
+        assert_eq!(packed.bits, 1);
+
+        packed.set(V3::new(1, 0, 0), 9);
+        assert_eq!(packed.bits, 2);
+
+        for i in 0 .. 20 {
+            packed.set(V3::new(3, 4, 4), 100 + i);
+        }
+        assert_eq!(packed.bits, 8);
+
+        assert_eq!(packed[V3::new(0, 0, 0)], 8);
+        assert_eq!(packed[V3::new(1, 0, 0)], 9);
+        assert_eq!(packed[V3::new(3, 4, 4)], 119);
+        assert_eq!(values(&packed).iter().filter(|x| **x == 7).count(), packed.volume() - 3);
+    }
+
+    #[test]
+    fn overwrite() {
+        let mut packed = Packed::new_filled(0);
+        packed.set(V3::new(1, 2, 3), 5);
+        assert_eq!(packed[V3::new(1, 2, 3)], 5);
+
+        packed.set(V3::new(1, 2, 3), 6);
+        packed.set(V3::new(2, 2, 2), 3);
+        assert_eq!(packed[V3::new(1, 2, 3)], 6);
+        assert_eq!(packed[V3::new(2, 2, 2)], 3);
+        assert_eq!(values(&packed).iter().filter(|x| **x != 0).count(), 2);
+    }
+
+    #[test]
+    fn round_trip() {
+        let dense: array3d::ArrayOwned<u16, Dims> = array3d::Array::generate(
+            |ijk| (ijk.x * 3 + ijk.y * ijk.z) as u16 % 11
+        );
+
+        let packed = Packed::from(&dense);
+        assert_eq!(packed.bits, 4);
+        assert!(values(&packed).into_iter().eq(dense.iter().copied()));
+
+        let mut dst: array3d::ArrayOwned<u16, Dims> = array3d::Array::new_filled(0);
+        let dims = V3::new(2, 2, 2);
+        packed.copy_into(V3::new(1, 1, 1), &mut dst.slice_mut(V3::zeros(), dims));
+        assert_eq!(dst[V3::new(1, 1, 1)], dense[V3::new(2, 2, 2)]);
+    }
+}
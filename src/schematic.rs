@@ -0,0 +1,172 @@
+
+use {
+    crate::{
+        block::Block,
+        math::*,
+        world_edit::Clipboard,
+    },
+    std::{
+        fs::File,
+        io::{self, BufReader, BufWriter, Read, Write},
+        path::Path,
+    },
+};
+
+/// Schematic files start with this, followed by a format version byte
+const MAGIC: &[u8; 4] = b"rkvs";
+const VERSION: u8 = 1;
+
+/// Anything larger is taken to be a corrupt file rather than a real schematic
+const MAX_VOLUME: usize = 1 << 24;
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("bad schematic: {}", what))
+}
+
+/// Writes a clipboard as a schematic
+///
+/// The format is the magic bytes and version, the dimensions as three
+/// little-endian `u32`s, then the blocks in array order, run-length encoded
+/// as (run length, block ID, block state) byte triples.
+pub fn write(mut writer: impl Write, clipboard: &Clipboard) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])?;
+    for dim in clipboard.dims().iter() {
+        writer.write_all(&(*dim as u32).to_le_bytes())?;
+    }
+
+    let mut run: Option<(Block, u8)> = None;
+    for &block in clipboard.iter() {
+        run = match run {
+            Some((prev, n)) if prev == block && n < u8::MAX => Some((prev, n + 1)),
+            Some((prev, n)) => {
+                writer.write_all(&[n, prev.id(), prev.state()])?;
+                Some((block, 1))
+            }
+            None => Some((block, 1)),
+        };
+    }
+
+    if let Some((prev, n)) = run {
+        writer.write_all(&[n, prev.id(), prev.state()])?;
+    }
+
+    Ok(())
+}
+
+pub fn read(mut reader: impl Read) -> io::Result<Clipboard> {
+    let mut magic = [0u8; 5];
+    reader.read_exact(&mut magic)?;
+    if &magic[.. 4] != MAGIC {
+        return Err(invalid("not a schematic"));
+    }
+    if magic[4] != VERSION {
+        return Err(invalid("unknown version"));
+    }
+
+    let mut dims = V3usize::zeros();
+    for dim in dims.iter_mut() {
+        let mut le = [0u8; 4];
+        reader.read_exact(&mut le)?;
+        *dim = u32::from_le_bytes(le) as usize;
+    }
+
+    let volume = dims.iter()
+        .try_fold(1usize, |product, dim| product.checked_mul(*dim))
+        .filter(|volume| *volume <= MAX_VOLUME)
+        .ok_or_else(|| invalid("too large"))?;
+
+    let mut runs = Vec::new();
+    reader.read_to_end(&mut runs)?;
+    if runs.len() % 3 != 0 {
+        return Err(invalid("truncated"));
+    }
+
+    let mut blocks = Vec::with_capacity(volume);
+    for run in runs.chunks_exact(3) {
+        // IDs are kept as they are, defined or not, like those in chunks
+        let block = Block::from_parts(run[1], run[2]);
+        let n = run[0] as usize;
+        if n == 0 || blocks.len() + n > volume {
+            return Err(invalid("bad run"));
+        }
+        blocks.extend(std::iter::repeat(block).take(n));
+    }
+
+    if blocks.len() != volume {
+        return Err(invalid("truncated"));
+    }
+
+    Ok(Clipboard::new_with_dims(dims, blocks))
+}
+
+pub fn save(path: impl AsRef<Path>, clipboard: &Clipboard) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer, clipboard)?;
+    writer.flush()
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<Clipboard> {
+    read(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterned() -> Clipboard {
+        Clipboard::generate_with_dims(V3usize::new(7, 3, 40), |ijk| {
+            if ijk.z > 20 { Block::EMPTY }
+            else          { Block::from_parts(((ijk.x + ijk.y) % 5) as u8, ijk.x as u8) }
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let clipboard = patterned();
+        let mut bytes = Vec::new();
+        write(&mut bytes, &clipboard).unwrap();
+
+        // long runs of air pack down
+        assert!(bytes.len() < clipboard.volume());
+        assert_eq!(read(&bytes[..]).unwrap(), clipboard);
+
+        let path = std::env::temp_dir().join(format!("rk-voxel-test-{}.schematic", std::process::id()));
+        save(&path, &clipboard).unwrap();
+        let loaded = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), clipboard);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let mut bytes = Vec::new();
+        write(&mut bytes, &patterned()).unwrap();
+
+        let rejected = |bytes: &[u8]| read(bytes).unwrap_err().kind() == io::ErrorKind::InvalidData;
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'x';
+        assert!(rejected(&wrong_magic));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = VERSION + 1;
+        assert!(rejected(&wrong_version));
+
+        assert!(rejected(&bytes[.. bytes.len() - 2]));
+        assert!(rejected(&bytes[.. bytes.len() - 3]));
+
+        let mut extra = bytes.clone();
+        extra.extend_from_slice(&[1, 0, 0]);
+        assert!(rejected(&extra));
+
+        let mut empty_run = bytes.clone();
+        let last_run = empty_run.len() - 3;
+        empty_run[last_run] = 0;
+        assert!(rejected(&empty_run));
+
+        let mut huge = bytes.clone();
+        huge[5 .. 9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(rejected(&huge));
+    }
+}
@@ -1,21 +1,37 @@
 #version 450
 
 layout(location = 0) uniform mat4 model_to_clip;
-layout(location = 1) uniform ivec3 selected;
 
 layout(location = 2) uniform vec2 tex_tile_dims;
 layout(location = 3) uniform vec2 tex_padding;
 layout(location = 4) uniform vec2 tex_stride;
+// how brightly the sky lights the world, from 0 at night to 1 at noon
+layout(location = 5) uniform float daylight;
+// toward whichever of the sun and moon is up
+layout(location = 6) uniform vec3 light_dir;
+layout(location = 7) uniform mat4 model_to_view;
 
 layout(location = 0) in ivec4 attr_pos_dir;
 layout(location = 1) in  vec4 attr_color;
 layout(location = 2) in ivec2 attr_tcoords;
-layout(location = 3) in ivec2 attr_rotate_;
+layout(location = 3) in ivec2 attr_rotate_ao;
+layout(location = 4) in ivec2 attr_extent;
+layout(location = 5) in ivec2 attr_light;
+// the part of the face covered, and how far it's sunk into the block, in
+// sixteenths of a block
+layout(location = 6) in ivec4 attr_bounds;
+layout(location = 7) in int   attr_depth;
 
 out vec4 color;
-out vec2 tcoords;
 out vec2 quad_coords;
-out float select;
+out vec3 block_coords;
+flat out vec2 tile_origin;
+flat out int  rotate;
+out float view_depth;
+
+int idot(ivec3 a, ivec3 b) {
+    return a.x * b.x + a.y * b.y + a.z * b.z;
+}
 
 void main() {
     // currently in fan (cyclic) order
@@ -28,39 +44,99 @@ void main() {
         ivec3[] (ivec3(0,1,1), ivec3(0,1,0), ivec3(0,0,0), ivec3(0,0,1))
     );
 
-    // currently in fan (cyclic) order
-    const ivec2 TEX_OFFSETS[] = ivec2[][] (
-        ivec2[] (ivec2(1,1), ivec2(0,1), ivec2(0,0), ivec2(1,0)),
-        ivec2[] (ivec2(0,1), ivec2(1,1), ivec2(1,0), ivec2(0,0)),
-        ivec2[] (ivec2(1,1), ivec2(0,1), ivec2(0,0), ivec2(1,0)),
-        ivec2[] (ivec2(1,1), ivec2(1,0), ivec2(0,0), ivec2(0,1)),
-        ivec2[] (ivec2(0,1), ivec2(0,0), ivec2(1,0), ivec2(1,1)),
-        ivec2[] (ivec2(1,1), ivec2(1,0), ivec2(0,0), ivec2(0,1))
+    // the axes along which a face's extent and texture coordinates run
+    const ivec3 U_AXES[] = ivec3[] (
+        ivec3(1,0,0), ivec3(1,0,0), ivec3(0,1,0),
+        ivec3(1,0,0), ivec3(1,0,0), ivec3(0,1,0)
+    );
+
+    const ivec3 V_AXES[] = ivec3[] (
+        ivec3(0,1,0), ivec3(0,0,1), ivec3(0,0,1),
+        ivec3(0,1,0), ivec3(0,0,1), ivec3(0,0,1)
+    );
+
+    // quads standing diagonally across a block, front and back of each
+    const ivec3 DIAGONALS[] = ivec3[][] (
+        ivec3[] (ivec3(1,1,1), ivec3(0,0,1), ivec3(0,0,0), ivec3(1,1,0)),
+        ivec3[] (ivec3(0,0,1), ivec3(1,1,1), ivec3(1,1,0), ivec3(0,0,0)),
+        ivec3[] (ivec3(0,1,1), ivec3(1,0,1), ivec3(1,0,0), ivec3(0,1,0)),
+        ivec3[] (ivec3(1,0,1), ivec3(0,1,1), ivec3(0,1,0), ivec3(1,0,0))
+    );
+    const float DIAGONAL_U[] = float[] (1.0, 0.0, 0.0, 1.0);
+
+    const float R = 0.70710678;
+    const vec3 NORMALS[] = vec3[] (
+        vec3(0,0, 1), vec3(0, 1,0), vec3( 1,0,0),
+        vec3(0,0,-1), vec3(0,-1,0), vec3(-1,0,0),
+        vec3(R,-R,0), vec3(-R,R,0), vec3(R,R,0), vec3(-R,-R,0)
     );
 
     ivec3 pos = attr_pos_dir.xyz;
     int   dir = attr_pos_dir.w;
 
-    ivec3 coords = pos + POS_OFFSETS[dir][gl_VertexID];
-    gl_Position = model_to_clip * vec4(vec3(coords), 1.0);
+    int ao[4] = int[] (3, 3, 3, 3);
+    int vertex = gl_VertexID;
+    vec3 coords;
 
-    float shade = 1.0 - (dir / 6.0);
-    color = vec4(shade * attr_color.rgb, attr_color.a);
+    if (dir < 6) {
+        ivec3 u_axis = U_AXES[dir];
+        ivec3 v_axis = V_AXES[dir];
+        ivec3 normal_axis = ivec3(1) - u_axis - v_axis;
 
-    int tc_rotate = attr_rotate_.x;
-    int tc_index = (gl_VertexID + tc_rotate) & 3;
-    vec2 quad_offs = vec2(TEX_OFFSETS[dir][tc_index]);
-    vec2 offs = tex_tile_dims * vec2(quad_offs.x, 1.0 - quad_offs.y);
-    tcoords = tex_padding
-            + attr_tcoords * tex_stride
-            + offs;
-    quad_coords = quad_offs;
-
-    if (pos == selected) {
-        select = 1.0;
+        // unpack per-corner occlusion, indexed by position along the tangent axes
+        for (int i = 0; i < 4; i++) {
+            ivec3 corner = POS_OFFSETS[dir][i];
+            int index = idot(corner, u_axis) + 2 * idot(corner, v_axis);
+            ao[i] = (attr_rotate_ao.y >> (2 * index)) & 3;
+        }
+
+        // split the quad along whichever diagonal joins the darker corners, so
+        // occlusion falls off symmetrically
+        int flip = (ao[0] + ao[2] > ao[1] + ao[3]) ? 1 : 0;
+        vertex = (gl_VertexID + flip) & 3;
+
+        // the quad spans its extent in whole tiles, less whatever its shape
+        // trims from the first and last
+        ivec3 corner = POS_OFFSETS[dir][vertex];
+        vec4 bounds = vec4(attr_bounds) / 16.0;
+        float depth = float(attr_depth) / 16.0;
+        float u = idot(corner, u_axis) == 1 ? float(attr_extent.x - 1) + bounds.z : bounds.x;
+        float v = idot(corner, v_axis) == 1 ? float(attr_extent.y - 1) + bounds.w : bounds.y;
+        float n = idot(corner, normal_axis) == 1 ? 1.0 - depth : depth;
+
+        coords = vec3(pos) + u * vec3(u_axis) + v * vec3(v_axis) + n * vec3(normal_axis);
+        quad_coords = vec2(u, v);
+
+        // centred on the face's block along the normal, so it floors correctly
+        block_coords = vec3(pos) + u * vec3(u_axis) + v * vec3(v_axis) + 0.5 * vec3(normal_axis);
     }
     else {
-        select = 0.0;
+        ivec3 corner = DIAGONALS[dir - 6][vertex];
+        coords = vec3(pos + corner);
+        quad_coords = vec2(DIAGONAL_U[vertex], float(corner.z));
+        block_coords = vec3(pos) + 0.5;
     }
-}
 
+    gl_Position = model_to_clip * vec4(coords, 1.0);
+    view_depth = -(model_to_view * vec4(coords, 1.0)).z;
+
+    // each light level is a fifth dimmer than the next, with a little
+    // ambient light so unlit places aren't pitch black
+    const float MAX_LEVEL = 15.0;
+    const float AMBIENT = 0.04;
+    // sky light is partly scattered from all over the sky, and partly
+    // straight from the sun or moon, falling brightest on faces turned to it
+    const float SCATTERED = 0.55;
+    float facing = max(dot(NORMALS[dir], light_dir), 0.0);
+    float diffuse = mix(SCATTERED, 1.0, facing);
+    float block_light = pow(0.8, MAX_LEVEL - attr_light.x);
+    float sky_light = pow(0.8, MAX_LEVEL - attr_light.y) * daylight * diffuse;
+    float light = mix(AMBIENT, 1.0, max(block_light, sky_light));
+
+    const float AO_CURVE[] = float[] (0.4, 0.6, 0.8, 1.0);
+    float shade = AO_CURVE[ao[vertex]] * light;
+    color = vec4(shade * attr_color.rgb, attr_color.a);
+
+    tile_origin = tex_padding + attr_tcoords * tex_stride;
+    rotate = attr_rotate_ao.x;
+}
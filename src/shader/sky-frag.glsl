@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 1) uniform vec3 horizon;
+layout(location = 2) uniform vec3 zenith;
+
+in vec3 view_dir;
+
+out vec4 frag;
+
+void main() {
+    // the horizon colour carries on below it, where the fog hides the ground
+    float elevation = max(normalize(view_dir).z, 0.0);
+    frag = vec4(mix(horizon, zenith, sqrt(elevation)), 1.0);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+// from clip space to a direction in the world, ignoring where the eye is
+layout(location = 0) uniform mat4 clip_to_world;
+
+out vec3 view_dir;
+
+void main() {
+    // one triangle covering the screen
+    vec2 corner = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(corner, 0.0, 1.0);
+
+    vec4 far = clip_to_world * vec4(corner, 1.0, 1.0);
+    view_dir = far.xyz / far.w;
+}
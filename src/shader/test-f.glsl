@@ -1,21 +1,62 @@
 #version 450
 
+layout(location = 1) uniform ivec3 selected;
+layout(location = 2) uniform vec2 tex_tile_dims;
+// view depths at which fog begins and at which it hides everything
+layout(location = 8) uniform vec2 fog_range;
+layout(location = 9) uniform vec3 fog_color;
+// texels less opaque than this are left out, so cutout blocks show through
+layout(location = 10) uniform float alpha_cutoff;
+
 layout(binding = 0) uniform sampler2D tex;
 
 in vec4 color;
-in vec2 tcoords;
 in vec2 quad_coords;
-in float select;
+in vec3 block_coords;
+flat in vec2 tile_origin;
+flat in int  rotate;
+in float view_depth;
 
 out vec4 frag;
 
+vec4 fogged(vec4 color) {
+    float fog = smoothstep(fog_range.x, fog_range.y, view_depth);
+    return vec4(mix(color.rgb, fog_color, fog), color.a);
+}
+
 void main() {
-    float edge_proximity = select * 2.0 * max(abs(quad_coords.x - 0.5), abs(quad_coords.y - 0.5));
+    ivec3 block = ivec3(floor(block_coords));
+    vec2 tile_coords = fract(quad_coords);
+
+    float select = (block == selected) ? 1.0 : 0.0;
+    float edge_proximity = select * 2.0 * max(abs(tile_coords.x - 0.5), abs(tile_coords.y - 0.5));
     if (edge_proximity > 0.95) {
-        frag = vec4(color.rgb, 1.0);
+        frag = fogged(vec4(color.rgb, 1.0));
+        return;
+    }
+
+    // 0 to 3 are fixed quarter turns, while 4 varies the orientation of
+    // each tile by hashing its block position
+    vec2 uv = tile_coords;
+    int turns = rotate;
+    if (rotate == 4) {
+        turns = ((block.x * 251) ^ (block.y * 199) ^ (block.z * 151)) & 3;
     }
-    else {
-        frag = color * texture(tex, tcoords);
+    for (int i = 0; i < turns; i++) {
+        uv = vec2(uv.y, 1.0 - uv.x);
     }
-}
 
+    vec2 tcoords = tile_origin + tex_tile_dims * vec2(uv.x, 1.0 - uv.y);
+
+    // take derivatives from the unwrapped coordinates to avoid seams at tile edges
+    vec2 grad_scale = tex_tile_dims;
+    vec4 texel = color * textureGrad(
+        tex, tcoords,
+        dFdx(quad_coords) * grad_scale,
+        dFdy(quad_coords) * grad_scale
+    );
+    if (texel.a < alpha_cutoff) {
+        discard;
+    }
+    frag = fogged(texel);
+}
@@ -0,0 +1,49 @@
+
+use {
+    crate::{
+        gl,
+        math::*,
+        mesher::VAO,
+        shader,
+    },
+};
+
+/// Draws the sky behind everything else, shading from the horizon up to
+/// the zenith
+pub struct Sky {
+    program: shader::Program,
+    /// Holds no arrays, as the triangle's corners come from vertex IDs
+    vao:     VAO,
+}
+
+impl Sky {
+    pub fn new() -> Result<Sky, shader::Error> {
+        static V_SHADER_SRC: &'static str = include_str!("shader/sky-vert.glsl");
+        static F_SHADER_SRC: &'static str = include_str!("shader/sky-frag.glsl");
+        let v_shader = shader::compile(shader::Stage::Vertex,   V_SHADER_SRC)?;
+        let f_shader = shader::compile(shader::Stage::Fragment, F_SHADER_SRC)?;
+        let program = shader::link(&[v_shader, f_shader])?;
+        Ok(Sky { program, vao: VAO::new() })
+    }
+
+    /// Fills the screen with sky, leaving the depth buffer alone
+    ///
+    /// `world_to_clip` is the camera's projection and turn, without moving
+    /// the eye. This leaves its own program bound.
+    pub fn draw(&self, world_to_clip: &M4, horizon: V3, zenith: V3) {
+        let clip_to_world = world_to_clip.try_inverse().unwrap_or_else(M4::identity);
+        self.vao.bind();
+        unsafe {
+            self.program.bind();
+            gl::UniformMatrix4fv(0, 1, gl::FALSE, clip_to_world.as_ptr());
+            gl::Uniform3fv(1, 1, horizon.as_ptr());
+            gl::Uniform3fv(2, 1, zenith.as_ptr());
+
+            gl::Disable(gl::DEPTH_TEST);
+            gl::DepthMask(gl::FALSE);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::DepthMask(gl::TRUE);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+}
@@ -94,6 +94,11 @@ impl<T> Stage<T> {
         else                  { None }
     }
 
+    /// Whether `abs` lies within the staged region around the center
+    pub fn covers(&self, abs: chunk::Coords) -> bool {
+        self.abs_to_ijk(abs).is_some()
+    }
+
     pub fn at_absolute(&self, abs: chunk::Coords) -> Option<&T> {
         self.elem_abs(abs)
             .and_then(|elem| elem.content.as_ref())
@@ -0,0 +1,134 @@
+
+use {
+    std::{
+        sync::{
+            Arc, Mutex, mpsc,
+            atomic::{AtomicBool, Ordering},
+        },
+        thread,
+    },
+};
+
+/// A fixed set of threads running the same function over queued jobs
+///
+/// Jobs are taken in submission order by whichever thread is free, so results
+/// may come back in any order. Dropping the pool discards the jobs still
+/// queued, waiting only for those already running.
+pub struct Pool<J, R> {
+    jobs:    Option<mpsc::Sender<J>>,
+    results: mpsc::Receiver<R>,
+    threads: Vec<thread::JoinHandle<()>>,
+    closed:  Arc<AtomicBool>,
+}
+
+impl<J, R> Pool<J, R> where J: Send + 'static, R: Send + 'static {
+    pub fn new<F> (name: &str, n_threads: usize, work: F) -> Pool<J, R>
+        where F: Fn(J) -> R + Send + Sync + 'static
+    {
+        let (job_tx, job_rx) = mpsc::channel::<J>();
+        let (result_tx, results) = mpsc::channel();
+
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let work = Arc::new(work);
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let threads = (0 .. n_threads.max(1))
+            .map(|i| {
+                let job_rx = job_rx.clone();
+                let result_tx = result_tx.clone();
+                let work = work.clone();
+                let closed = closed.clone();
+                thread::Builder::new()
+                    .name(format!("{}-{}", name, i))
+                    .spawn(move || loop {
+                        let job = match job_rx.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_)  => { break; }
+                        };
+                        if closed.load(Ordering::Relaxed) {
+                            break;
+                        }
+
+                        if result_tx.send(work(job)).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("error spawning worker thread")
+            })
+            .collect();
+
+        Pool { jobs: Some(job_tx), results, threads, closed }
+    }
+
+    /// A thread count leaving one core free for the main thread
+    pub fn default_thread_count() -> usize {
+        thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(1))
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    pub fn submit(&self, job: J) {
+        self.jobs.as_ref()
+            .unwrap()
+            .send(job)
+            .expect("worker threads have exited");
+    }
+
+    /// Takes the results which are ready, without waiting
+    pub fn try_iter(&self) -> impl Iterator<Item = R> + '_ {
+        self.results.try_iter()
+    }
+}
+
+impl<J, R> Drop for Pool<J, R> {
+    fn drop(&mut self) {
+        // closing the queue lets the threads finish their current jobs and
+        // exit, leaving whatever is still queued undone
+        self.closed.store(true, Ordering::Relaxed);
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_every_job() {
+        let pool = Pool::new("test", 3, |x: u32| x * 2);
+        for x in 0 .. 100 {
+            pool.submit(x);
+        }
+
+        let mut results = Vec::new();
+        while results.len() < 100 {
+            results.extend(pool.try_iter());
+            thread::yield_now();
+        }
+
+        results.sort();
+        assert_eq!(results, (0 .. 100).map(|x| x * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn dropping_discards_queued_jobs() {
+        let ran = Arc::new(Mutex::new(0));
+        let pool = {
+            let ran = ran.clone();
+            Pool::new("test", 1, move |_: ()| {
+                thread::sleep(std::time::Duration::from_millis(20));
+                *ran.lock().unwrap() += 1;
+            })
+        };
+        for _ in 0 .. 50 {
+            pool.submit(());
+        }
+
+        drop(pool);
+        assert!(*ran.lock().unwrap() < 5);
+    }
+}
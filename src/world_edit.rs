@@ -0,0 +1,415 @@
+
+use crate::{
+    array3d::{ArrayOwned, DynamicDims},
+    block::Block,
+    chunk::BlockCoords,
+    math::*,
+    mesher::Direction,
+};
+
+/// Blocks copied out of the world, ready to be pasted back into it
+pub type Clipboard = ArrayOwned<Block, DynamicDims>;
+
+/// A world-edit action chosen by the player
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tool {
+    MarkFirst,
+    MarkSecond,
+    Fill,
+    Replace,
+    Hollow,
+    Sphere,
+    Cylinder,
+    Copy,
+    Paste,
+    /// Turns the clipboard a quarter turn anticlockwise about Z
+    Rotate,
+    /// Flips the clipboard along X
+    Mirror,
+    SaveSchematic,
+    LoadSchematic,
+}
+
+/// A region marked out by two opposite corner blocks
+pub struct Selection {
+    corners: [Option<BlockCoords>; 2],
+}
+
+impl Selection {
+    pub fn new() -> Selection {
+        Selection { corners: [None, None] }
+    }
+
+    pub fn mark(&mut self, corner: usize, at: BlockCoords) {
+        self.corners[corner] = Some(at);
+    }
+
+    /// The box enclosing both corner blocks, once both are marked
+    pub fn bounds(&self) -> Option<Box3> {
+        let a = self.corners[0]?.unwrap();
+        let b = self.corners[1]?.unwrap();
+        let mins = a.zip_map(&b, i32::min).map(|x| x as f32);
+        let maxs = a.zip_map(&b, i32::max).map(|x| x as f32 + 1.);
+        Some(Box3::new(mins.into(), maxs.into()))
+    }
+}
+
+/// A set of blocks, being those whose centres lie within it
+#[derive(Clone, Copy)]
+pub enum Shape {
+    Box(Box3),
+    Sphere { center: P3, radius: f32 },
+    /// Upright, standing on `base`
+    Cylinder { base: P3, radius: f32, height: f32 },
+}
+
+impl Shape {
+    pub fn contains(&self, point: P3) -> bool {
+        match *self {
+            Shape::Box(bounds) => bounds.contains(point),
+
+            Shape::Sphere { center, radius } => {
+                (point - center).norm_squared() <= radius * radius
+            }
+
+            Shape::Cylinder { base, radius, height } => {
+                let offset = point - base;
+                offset.xy().norm_squared() <= radius * radius
+                    && (0. ..= height).contains(&offset.z)
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Box3 {
+        match *self {
+            Shape::Box(bounds) => bounds,
+
+            Shape::Sphere { center, radius } => Box3::new(
+                center - V3::repeat(radius),
+                center + V3::repeat(radius)
+            ),
+
+            Shape::Cylinder { base, radius, height } => Box3::new(
+                base - V3::new(radius, radius, 0.),
+                base + V3::new(radius, radius, height)
+            ),
+        }
+    }
+
+    /// Blocks which may be in the shape, whether or not they are
+    pub fn bounding_volume(&self) -> usize {
+        let bounds = self.bounds();
+        let dims = bounds.maxs() - bounds.mins();
+        dims.iter()
+            .map(|x| x.ceil() as usize + 1)
+            .product()
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = BlockCoords> + '_ {
+        let bounds = self.bounds();
+        let mins = bounds.mins().coords.map(|x| x.floor() as i32);
+        let maxs = bounds.maxs().coords.map(|x| x.ceil() as i32);
+
+        SpaceIter::new(mins, maxs)
+            .map(|at| BlockCoords::new(at.into()))
+            .filter(move |block| self.contains(centre(*block)))
+    }
+
+    /// Whether a block of the shape has no face exposed to the outside of it
+    fn is_interior(&self, block: BlockCoords) -> bool {
+        [V3i32::x(), V3i32::y(), V3i32::z(), -V3i32::x(), -V3i32::y(), -V3i32::z()].iter()
+            .all(|step| self.contains(centre(block + *step)))
+    }
+}
+
+fn centre(block: BlockCoords) -> P3 {
+    (block.unwrap_f32() + V3::repeat(0.5)).into()
+}
+
+#[derive(Clone, Copy)]
+pub enum Operation {
+    Fill(Block),
+    /// Replaces blocks of the same kind as `from`, whatever their state
+    Replace { from: Block, to: Block },
+    /// Empties the shape, apart from a shell one block thick
+    Hollow,
+}
+
+/// Works out which blocks an operation over a shape changes, and to what
+///
+/// `block_at` reads the current contents of the world.
+pub fn plan(shape: &Shape, operation: Operation, mut block_at: impl FnMut(BlockCoords) -> Block)
+    -> Vec<(BlockCoords, Block)>
+{
+    shape.blocks()
+        .filter_map(|block| {
+            let new = match operation {
+                Operation::Fill(with) => with,
+
+                Operation::Replace { from, to } => {
+                    if !block_at(block).same_kind(from) { return None; }
+                    to
+                }
+
+                Operation::Hollow => {
+                    if !shape.is_interior(block) { return None; }
+                    Block::EMPTY
+                }
+            };
+
+            if block_at(block) == new { None }
+            else                      { Some((block, new)) }
+        })
+        .collect()
+}
+
+/// Copies the blocks of a region into a clipboard
+pub fn copy(bounds: &Box3, mut block_at: impl FnMut(BlockCoords) -> Block) -> Clipboard {
+    let mins = bounds.mins().coords.map(|x| x.floor() as i32);
+    let maxs = bounds.maxs().coords.map(|x| x.ceil() as i32);
+    let dims = (maxs - mins).map(|x| x.max(0) as usize);
+
+    Clipboard::generate_with_dims(dims, |ijk| {
+        block_at(BlockCoords::new((mins + ijk.map(|x| x as i32)).into()))
+    })
+}
+
+/// Turns a clipboard `quarter_turns` quarter turns anticlockwise about Z,
+/// looking down on it
+pub fn rotate(clipboard: &Clipboard, quarter_turns: i32) -> Clipboard {
+    let turns = quarter_turns.rem_euclid(4);
+    let dims = clipboard.dims();
+    let new_dims = if turns % 2 == 0 { dims } else { V3usize::new(dims.y, dims.x, dims.z) };
+
+    let to = |mut ijk: V3usize| {
+        for turn in 0 .. turns {
+            let h = if turn % 2 == 0 { dims.y } else { dims.x };
+            ijk = V3usize::new(h - 1 - ijk.y, ijk.x, ijk.z);
+        }
+        ijk
+    };
+    let turn = |mut v: V3i32| {
+        for _ in 0 .. turns {
+            v = V3i32::new(-v.y, v.x, v.z);
+        }
+        v
+    };
+    transform(clipboard, new_dims, to, turn)
+}
+
+/// Flips a clipboard end to end along X, mirroring it in a plane through Z
+pub fn mirror(clipboard: &Clipboard) -> Clipboard {
+    let dims = clipboard.dims();
+    transform(
+        clipboard, dims,
+        |ijk| V3usize::new(dims.x - 1 - ijk.x, ijk.y, ijk.z),
+        |v| V3i32::new(-v.x, v.y, v.z),
+    )
+}
+
+/// Moves each block to `to` its position, and turns the way it faces by
+/// `turn`
+fn transform(
+    clipboard: &Clipboard,
+    new_dims:  V3usize,
+    to:        impl Fn(V3usize) -> V3usize,
+    turn:      impl Fn(V3i32) -> V3i32,
+)
+    -> Clipboard
+{
+    let mut out = Clipboard::new_with_dims(new_dims, vec![Block::EMPTY; clipboard.volume()]);
+    for (ijk, block) in clipboard.indexed_iter() {
+        let facing = Direction::from_normal(turn(block.facing().normal())).unwrap();
+        out[to(ijk)] = block.with_facing(facing);
+    }
+    out
+}
+
+/// Works out which blocks pasting a clipboard with its least corner at
+/// `origin` changes
+pub fn paste(clipboard: &Clipboard, origin: BlockCoords, mut block_at: impl FnMut(BlockCoords) -> Block)
+    -> Vec<(BlockCoords, Block)>
+{
+    clipboard.indexed_iter()
+        .map(|(ijk, block)| (origin + ijk.map(|x| x as i32), *block))
+        .filter(|(at, block)| block_at(*at) != *block)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::block::stock::*,
+        std::collections::HashMap,
+    };
+
+    fn at(x: i32, y: i32, z: i32) -> BlockCoords {
+        BlockCoords::new(P3::new(x, y, z))
+    }
+
+    fn selection(a: BlockCoords, b: BlockCoords) -> Shape {
+        let mut selection = Selection::new();
+        selection.mark(0, a);
+        assert!(selection.bounds().is_none());
+        selection.mark(1, b);
+        Shape::Box(selection.bounds().unwrap())
+    }
+
+    #[test]
+    fn fill_selection() {
+        // corners given in any order
+        let shape = selection(at(3, -1, 2), at(0, 1, 0));
+        let changes = plan(&shape, Operation::Fill(STONE), |_| Block::EMPTY);
+
+        assert_eq!(changes.len(), 4 * 3 * 3);
+        assert!(changes.iter().all(|(_, block)| *block == STONE));
+        assert!(changes.iter().any(|(b, _)| *b == at(0, -1, 0)));
+        assert!(changes.iter().any(|(b, _)| *b == at(3, 1, 2)));
+
+        // nothing to do where the blocks are already right
+        assert!(plan(&shape, Operation::Fill(STONE), |_| STONE).is_empty());
+    }
+
+    #[test]
+    fn replace() {
+        let shape = selection(at(0, 0, 0), at(3, 3, 0));
+        let world = |b: BlockCoords| {
+            let x = b.unwrap().x;
+            if x % 2 == 0 { GRASS.with_variant(x as u8) } else { SOIL }
+        };
+
+        let changes = plan(&shape, Operation::Replace { from: GRASS, to: STONE }, world);
+        assert_eq!(changes.len(), 8);
+        assert!(changes.iter().all(|(b, block)| b.unwrap().x % 2 == 0 && *block == STONE));
+    }
+
+    #[test]
+    fn hollow() {
+        let shape = selection(at(0, 0, 0), at(3, 3, 3));
+        let mut world: HashMap<BlockCoords, Block> = shape.blocks()
+            .map(|b| (b, STONE))
+            .collect();
+
+        let changes = plan(&shape, Operation::Hollow, |b| world[&b]);
+        assert_eq!(changes.len(), 2 * 2 * 2);
+        for (b, block) in changes {
+            assert!(b.unwrap().iter().all(|x| (1 ..= 2).contains(x)));
+            world.insert(b, block);
+        }
+
+        assert_eq!(world.values().filter(|b| b.is_nonempty()).count(), 64 - 8);
+    }
+
+    #[test]
+    fn brushes() {
+        let center = centre(at(10, 10, 10));
+        let sphere = Shape::Sphere { center, radius: 2. };
+        let blocks: Vec<BlockCoords> = sphere.blocks().collect();
+
+        // the centre, six steps along each axis, and the rings between
+        assert!(blocks.contains(&at(10, 10, 10)));
+        assert!(blocks.contains(&at(12, 10, 10)) && blocks.contains(&at(8, 10, 10)));
+        assert!(!blocks.contains(&at(12, 12, 10)));
+        assert_eq!(blocks.len(), 33);
+
+        let cylinder = Shape::Cylinder { base: center, radius: 1., height: 3. };
+        let blocks: Vec<BlockCoords> = cylinder.blocks().collect();
+        assert_eq!(blocks.len(), 5 * 4);
+        assert!(blocks.contains(&at(10, 11, 13)));
+        assert!(!blocks.contains(&at(10, 10, 9)));
+        assert!(!blocks.contains(&at(11, 11, 10)));
+
+        // the hollow of a solid cylinder is its core
+        let hollow = plan(&cylinder, Operation::Hollow, |_| STONE);
+        assert_eq!(hollow.len(), 2);
+    }
+
+    /// An L of stone two long in X, three in Y, with grass at its foot
+    fn ell() -> Clipboard {
+        let shape = selection(at(5, 5, 5), at(6, 7, 5));
+        let world = |b: BlockCoords| {
+            let offset: [i32; 3] = (b.unwrap() - V3i32::repeat(5)).into();
+            match offset {
+                [0, 0, 0] => GRASS,
+                [_, 0, 0] | [0, _, 0] => STONE,
+                _ => Block::EMPTY,
+            }
+        };
+
+        match shape {
+            Shape::Box(bounds) => copy(&bounds, world),
+            _ => unreachable!(),
+        }
+    }
+
+    fn blocks(clipboard: &Clipboard) -> Vec<([usize; 3], Block)> {
+        clipboard.indexed_iter()
+            .filter(|(_, b)| b.is_nonempty())
+            .map(|(ijk, b)| (ijk.into(), *b))
+            .collect()
+    }
+
+    #[test]
+    fn copy_and_transform() {
+        let clipboard = ell();
+        assert_eq!(clipboard.dims(), V3usize::new(2, 3, 1));
+        assert_eq!(blocks(&clipboard), vec![
+            ([0, 0, 0], GRASS),
+            ([0, 1, 0], STONE),
+            ([0, 2, 0], STONE),
+            ([1, 0, 0], STONE),
+        ]);
+
+        // the arm along X swings round to point along Y
+        let turned = rotate(&clipboard, 1);
+        assert_eq!(turned.dims(), V3usize::new(3, 2, 1));
+        assert_eq!(blocks(&turned), vec![
+            ([0, 0, 0], STONE),
+            ([1, 0, 0], STONE),
+            ([2, 0, 0], GRASS),
+            ([2, 1, 0], STONE),
+        ]);
+
+        assert_eq!(rotate(&clipboard, 4), clipboard);
+        assert_eq!(rotate(&clipboard, -1), rotate(&clipboard, 3));
+        assert_eq!(rotate(&rotate(&clipboard, 1), 2), rotate(&clipboard, 3));
+
+        let flipped = mirror(&clipboard);
+        assert_eq!(blocks(&flipped), vec![
+            ([0, 0, 0], STONE),
+            ([1, 0, 0], GRASS),
+            ([1, 1, 0], STONE),
+            ([1, 2, 0], STONE),
+        ]);
+        assert_eq!(mirror(&flipped), clipboard);
+    }
+
+    #[test]
+    fn turned_blocks_turn_with_the_clipboard() {
+        use Direction::*;
+        let log = |facing| Clipboard::new_with_dims(V3usize::new(1, 1, 1), vec![TREE_TRUNK.with_facing(facing)]);
+        let facing = |clipboard: Clipboard| clipboard[V3usize::zeros()].facing();
+
+        assert_eq!(facing(rotate(&log(XOut), 1)), YOut);
+        assert_eq!(facing(rotate(&log(YOut), 1)), XIn);
+        assert_eq!(facing(rotate(&log(XOut), 2)), XIn);
+        assert_eq!(facing(rotate(&log(ZIn), 1)), ZIn);
+        assert_eq!(facing(mirror(&log(XIn))), XOut);
+        assert_eq!(facing(mirror(&log(YIn))), YIn);
+    }
+
+    #[test]
+    fn paste_changes() {
+        let clipboard = ell();
+        let mut world: HashMap<BlockCoords, Block> = HashMap::new();
+        world.insert(at(-10, 1, 0), STONE);
+
+        let changes = paste(&clipboard, at(-10, 0, 0), |b| *world.get(&b).unwrap_or(&Block::EMPTY));
+        assert_eq!(changes, vec![
+            (at(-10, 0, 0), GRASS),
+            (at(-10, 2, 0), STONE),
+            (at(-9, 0, 0), STONE),
+        ]);
+    }
+}